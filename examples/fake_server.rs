@@ -1,15 +1,23 @@
+use futures_util::TryStreamExt;
 use serde::{Deserialize, Serialize};
 use serde_json::{Value::Object, json};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, hash_map::Entry},
     convert::Infallible,
     env,
     sync::{
         atomic::{AtomicUsize, Ordering},
         {Arc, Mutex},
     },
+    time::Duration,
+};
+use warp::{
+    Filter, Rejection, Reply,
+    http::{HeaderMap, Method, StatusCode},
+    hyper::body::Buf,
+    multipart::FormData,
+    reject, reply,
 };
-use warp::{Filter, Rejection, Reply, http::StatusCode, reject, reply};
 
 type Db = Arc<Mutex<HashMap<usize, serde_json::Value>>>;
 
@@ -48,6 +56,66 @@ fn with_auth() -> impl Filter<Extract = (), Error = Rejection> + Clone {
         .untuple_one()
 }
 
+// HTTP Basic credentials are fixed: `tstit:secret`
+fn with_basic_auth() -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::<String>("Authorization")
+        .and_then(|credentials: String| async move {
+            if credentials == "Basic dHN0aXQ6c2VjcmV0" {
+                Ok(())
+            } else {
+                Err(reject::custom(AuthError))
+            }
+        })
+        .untuple_one()
+}
+
+// the token of TSTIT_TKN env var, prefixed with `Bearer ` scheme
+fn with_bearer_auth() -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::<String>("Authorization")
+        .and_then(|credentials: String| async move {
+            let token = env::var("TSTIT_TKN")
+                .map_err(|_| "TSTIT_TKN env var is not set!")
+                .unwrap();
+            let token = token.strip_prefix("Bearer ").unwrap_or(&token);
+            if credentials == format!("Bearer {token}") {
+                Ok(())
+            } else {
+                Err(reject::custom(AuthError))
+            }
+        })
+        .untuple_one()
+}
+
+// GET requests with a body (even an empty one, i.e. of `curl -d ''`) are rejected
+fn without_get_body() -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::method()
+        .and(warp::header::optional::<String>("content-length"))
+        .and(warp::header::optional::<String>("transfer-encoding"))
+        .and_then(
+            |method: Method, length: Option<String>, encoding: Option<String>| async move {
+                if method == Method::GET && (length.is_some() || encoding.is_some()) {
+                    Err(reject::custom(GetBodyError))
+                } else {
+                    Ok(())
+                }
+            },
+        )
+        .untuple_one()
+}
+
+// API key is fixed: `tstit-key`
+fn with_api_key() -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::<String>("X-Api-Key")
+        .and_then(|key: String| async move {
+            if key == "tstit-key" {
+                Ok(())
+            } else {
+                Err(reject::custom(AuthError))
+            }
+        })
+        .untuple_one()
+}
+
 #[tokio::main]
 async fn main() {
     let db: Db = Arc::new(Mutex::new(HashMap::new()));
@@ -78,13 +146,118 @@ async fn main() {
             .or(with_auth()
                 .and(warp::path::param())
                 .and(with_db(db.clone()))
+                .and(warp::header::optional::<String>("x-request-id"))
+                .and(warp::header::headers_cloned())
                 .and_then(get_customer_by_id))
             .or(with_auth()
+                .and(warp::path::end())
                 .and(with_db(db.clone()))
                 .and_then(get_all_customers)),
     );
 
-    let routes = customer_routes.recover(handle_rejection);
+    // responds after the given delay, in milliseconds
+    let slow_route = warp::path!("v1" / "slow" / u64).and_then(slow_response);
+
+    // fails the first given number of calls, then succeeds
+    let flaky_route = warp::path!("v1" / "flaky" / usize).and_then(flaky_response);
+
+    // responds in `{ "status": "ok", "result": ... }` envelope
+    let health_route = warp::path!("v1" / "health")
+        .and(with_db(db.clone()))
+        .and_then(get_health);
+
+    // responds with the bare customer, without envelope
+    let raw_route = warp::path!("v1" / "raw" / "customer" / usize)
+        .and(with_db(db.clone()))
+        .and_then(get_raw_customer);
+
+    // form-urlencoded login, echoing the user back
+    let login_route = warp::path!("v1" / "login")
+        .and(warp::post())
+        .and(warp::body::form())
+        .and_then(login);
+
+    // raw text body, echoed back
+    let echo_route = warp::path!("v1" / "echo")
+        .and(warp::post())
+        .and(warp::header::optional::<String>("content-type"))
+        .and(warp::body::bytes())
+        .and_then(echo);
+
+    // echoes the path segment back, percent-decoded
+    let echo_path_route = warp::path!("v1" / "echo" / String)
+        .and(warp::get())
+        .and_then(echo_path);
+
+    // multipart upload, responding with sizes of files and values of fields
+    let upload_route = warp::path!("v1" / "upload")
+        .and(warp::post())
+        .and(warp::multipart::form().max_length(1024 * 1024))
+        .and_then(upload);
+
+    // redirects to the canonical customer URL
+    let redirect_route = warp::path!("v1" / "redirect" / "customer" / usize).map(|id: usize| {
+        let location: warp::http::Uri = format!("/v1/customer/{id}").parse().unwrap();
+        warp::redirect::found(location)
+    });
+
+    // guarded by HTTP Basic auth, instead of the token
+    let basic_route = warp::path!("v1" / "basic" / "whoami")
+        .and(with_basic_auth())
+        .map(|| Response::new(json!({"username": "tstit"})));
+
+    // guarded by the token with explicit `Bearer ` scheme
+    let bearer_route = warp::path!("v1" / "bearer" / "whoami")
+        .and(with_bearer_auth())
+        .map(|| Response::new(json!({"scheme": "Bearer"})));
+
+    // guarded by API key of non-standard header
+    let api_key_route = warp::path!("v1" / "apikey" / "whoami")
+        .and(with_api_key())
+        .map(|| Response::new(json!({"header": "X-Api-Key"})));
+
+    // synthetic list of the given number of customers, megabytes large
+    let large_route = warp::path!("v1" / "large" / usize).map(|count: usize| {
+        let customers: Vec<serde_json::Value> = (0..count)
+            .map(|id| {
+                json!({
+                    "id": id,
+                    "firstname": format!("John{id}"),
+                    "lastname": "Dow",
+                    "bio": "lorem ipsum dolor sit amet ".repeat(8),
+                })
+            })
+            .collect();
+        Response::new(json!(customers))
+    });
+
+    // guarded by the session cookie of the login
+    let session_route = warp::path!("v1" / "session" / "whoami")
+        .and(warp::cookie::<String>("session"))
+        .and_then(|session: String| async move {
+            if session == "tstit-session" {
+                Ok(Response::new(json!({"session": "valid"})))
+            } else {
+                Err(reject::custom(AuthError))
+            }
+        });
+
+    let routes = customer_routes
+        .or(slow_route)
+        .or(flaky_route)
+        .or(health_route)
+        .or(raw_route)
+        .or(login_route)
+        .or(echo_route)
+        .or(echo_path_route)
+        .or(upload_route)
+        .or(redirect_route)
+        .or(basic_route)
+        .or(bearer_route)
+        .or(api_key_route)
+        .or(session_route)
+        .or(large_route);
+    let routes = without_get_body().and(routes).recover(handle_rejection);
 
     let url = env::var("TSTIT_URL")
         .map_err(|_| "TSTIT_URL env var is not set!")
@@ -107,15 +280,36 @@ async fn create_customer(customer: serde_json::Value, db: Db) -> Result<impl Rep
     let mut db_lock = db.lock().unwrap();
     let id = generate_id();
     db_lock.insert(id, customer);
-    Ok(Response::new(json!(id)).with_status(StatusCode::CREATED))
+    let reply = Response::new(json!(id)).with_status(StatusCode::CREATED);
+    Ok(reply::with_header(
+        reply,
+        "location",
+        format!("/v1/customer/{id}"),
+    ))
 }
 
-async fn get_customer_by_id(id: usize, db: Db) -> Result<impl Reply, Rejection> {
+async fn get_customer_by_id(
+    id: usize,
+    db: Db,
+    request_id: Option<String>,
+    headers: HeaderMap,
+) -> Result<impl Reply, Rejection> {
     println!("get_customer_by_id: {id}");
     let db_lock = db.lock().unwrap();
     match db_lock.get(&id) {
-        Some(customer) => Ok(Response::new(customer.clone())),
-        None => Err(reject::not_found()),
+        Some(customer) => {
+            let reply = echo_request_id(Response::new(customer.clone()), request_id);
+            // the name of tenant header is templated by the customer id: `X-Tenant-2-Key`
+            let tenant_key = headers
+                .get(format!("x-tenant-{id}-key"))
+                .and_then(|key| key.to_str().ok());
+            let reply: Box<dyn Reply> = match tenant_key {
+                Some(key) => Box::new(reply::with_header(reply, "x-tenant-key", key)),
+                None => reply,
+            };
+            Ok(reply)
+        }
+        None => Err(reject::custom(NoCustomer)),
     }
 }
 
@@ -133,11 +327,12 @@ async fn update_customer(
 ) -> Result<impl Reply, Rejection> {
     println!("update_customer: {id}");
     let mut db_lock = db.lock().unwrap();
-    if db_lock.contains_key(&id) {
-        db_lock.insert(id, customer);
-        Ok(Response::new(json!(id)))
-    } else {
-        Err(reject::not_found())
+    match db_lock.entry(id) {
+        Entry::Occupied(mut entry) => {
+            entry.insert(customer);
+            Ok(Response::new(json!(id)))
+        }
+        Entry::Vacant(_) => Err(reject::custom(NoCustomer)),
     }
 }
 
@@ -157,7 +352,7 @@ async fn patch_customer(
             }
             Ok(Response::new(json!(id)))
         }
-        None => Err(reject::not_found()),
+        None => Err(reject::custom(NoCustomer)),
     }
 }
 
@@ -168,7 +363,137 @@ async fn delete_customer(id: usize, db: Db) -> Result<impl Reply, Rejection> {
         db_lock.remove(&id);
         Ok(Response::new(json!(id)))
     } else {
-        Err(reject::not_found())
+        Err(reject::custom(NoCustomer))
+    }
+}
+
+async fn slow_response(delay_ms: u64) -> Result<impl Reply, Rejection> {
+    println!("slow_response: {delay_ms}");
+    tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+    Ok(Response::new(json!(delay_ms)))
+}
+
+async fn flaky_response(failures: usize) -> Result<impl Reply, Rejection> {
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+    let call = CALLS.fetch_add(1, Ordering::Relaxed);
+    println!("flaky_response: call {call}");
+    if call < failures {
+        let code = StatusCode::SERVICE_UNAVAILABLE;
+        Ok(Response {
+            code: code.as_u16() as usize,
+            data: json!("SERVICE_UNAVAILABLE"),
+        }
+        .with_status(code))
+    } else {
+        Ok(Response::new(json!(call)).with_status(StatusCode::OK))
+    }
+}
+
+async fn get_health(db: Db) -> Result<impl Reply, Rejection> {
+    println!("get_health");
+    let customers = db.lock().unwrap().len();
+    Ok(reply::json(&json!({
+        "status": "ok",
+        "result": {"healthy": true, "customers": customers, "maintenance": null}
+    })))
+}
+
+async fn get_raw_customer(id: usize, db: Db) -> Result<impl Reply, Rejection> {
+    println!("get_raw_customer: {id}");
+    let db_lock = db.lock().unwrap();
+    match db_lock.get(&id) {
+        Some(customer) => Ok(reply::json(&json!({
+            "id": id,
+            "firstname": customer["firstname"],
+            "lastname": customer["lastname"]
+        }))),
+        None => Err(reject::custom(NoCustomer)),
+    }
+}
+
+async fn login(form: HashMap<String, String>) -> Result<impl Reply, Rejection> {
+    println!("login: {:?}", form.get("username"));
+    match (form.get("username"), form.get("password")) {
+        (Some(username), Some(password)) if !password.is_empty() => Ok(reply::with_header(
+            Response::new(json!({"username": username})),
+            "set-cookie",
+            "session=tstit-session; Path=/; HttpOnly",
+        )),
+        _ => Err(reject::custom(AuthError)),
+    }
+}
+
+async fn echo(
+    content_type: Option<String>,
+    body: warp::hyper::body::Bytes,
+) -> Result<impl Reply, Rejection> {
+    println!("echo: {content_type:?}");
+    Ok(Response::new(json!({
+        "content_type": content_type,
+        "text": String::from_utf8_lossy(&body)
+    })))
+}
+
+async fn upload(mut form: FormData) -> Result<impl Reply, Rejection> {
+    let mut fields = serde_json::Map::new();
+    // each part is read before the next one, as parts of the form can't be held at once
+    while let Some(part) = form
+        .try_next()
+        .await
+        .map_err(|_| reject::custom(UploadError))?
+    {
+        let name = part.name().to_string();
+        let filename = part.filename().map(str::to_string);
+        let content = part
+            .stream()
+            .try_fold(Vec::new(), |mut content, buf| async move {
+                content.extend_from_slice(buf.chunk());
+                Ok(content)
+            })
+            .await
+            .map_err(|_| reject::custom(UploadError))?;
+        println!("upload: {name}, {} bytes", content.len());
+        let field = match filename {
+            Some(filename) => json!({"filename": filename, "size": content.len()}),
+            None => json!(String::from_utf8_lossy(&content)),
+        };
+        fields.insert(name, field);
+    }
+    Ok(Response::new(Object(fields)))
+}
+
+// X-Request-Id of the request (if any) is echoed back in the response headers
+async fn echo_path(segment: String) -> Result<impl Reply, Rejection> {
+    println!("echo_path: {segment}");
+    Ok(Response::new(json!({"text": percent_decode(&segment)})))
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match text
+            .get(i + 1..i + 3)
+            .map(|hex| u8::from_str_radix(hex, 16))
+        {
+            Some(Ok(byte)) if bytes[i] == b'%' => {
+                decoded.push(byte);
+                i += 3;
+            }
+            _ => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+fn echo_request_id(reply: impl Reply + 'static, request_id: Option<String>) -> Box<dyn Reply> {
+    match request_id {
+        Some(request_id) => Box::new(reply::with_header(reply, "x-request-id", request_id)),
+        None => Box::new(reply),
     }
 }
 
@@ -177,19 +502,34 @@ fn generate_id() -> usize {
     COUNTER_ID.fetch_add(1, Ordering::Relaxed)
 }
 
+// unlike `reject::not_found()`, isn't outranked by `MethodNotAllowed` of the sibling routes
+#[derive(Debug)]
+struct NoCustomer;
+impl reject::Reject for NoCustomer {}
+
 #[derive(Debug)]
 struct AuthError;
 impl reject::Reject for AuthError {}
 
+#[derive(Debug)]
+struct UploadError;
+impl reject::Reject for UploadError {}
+
+#[derive(Debug)]
+struct GetBodyError;
+impl reject::Reject for GetBodyError {}
+
 async fn handle_rejection(err: Rejection) -> Result<impl Reply, Infallible> {
     eprintln!("handle_rejection: {:?}", err);
-    let (code, message) = if err.is_not_found() {
+    let (code, message) = if err.is_not_found() || err.find::<NoCustomer>().is_some() {
         (StatusCode::NOT_FOUND, "NOT_FOUND")
     } else if err.find::<AuthError>().is_some() {
         (StatusCode::UNAUTHORIZED, "UNAUTHORIZED")
     } else if err
         .find::<warp::filters::body::BodyDeserializeError>()
         .is_some()
+        || err.find::<UploadError>().is_some()
+        || err.find::<GetBodyError>().is_some()
     {
         (StatusCode::BAD_REQUEST, "BAD_REQUEST")
     } else {
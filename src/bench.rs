@@ -0,0 +1,180 @@
+use argh::FromArgs;
+use log::{error, info};
+use std::{
+    error::Error,
+    path::PathBuf,
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    config::{self, Layer},
+    engine::{TestEngine, Vars},
+    plan::TestPlan,
+};
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// tstit bench - hammers the endpoint of a single testplan, reporting throughput and latencies
+struct BenchArgs {
+    #[argh(positional)]
+    /// path to testplan TOML file
+    plan: PathBuf,
+
+    #[argh(
+        option,
+        default = "Duration::from_secs(10)",
+        from_str_fn(parse_duration)
+    )]
+    /// how long to run, i.e. 30s, 500ms, 2m (default: 10s)
+    duration: Duration,
+    #[argh(option, default = "1")]
+    /// number of concurrent workers (default: 1)
+    concurrency: usize,
+    #[argh(switch)]
+    /// count responses with empty or non-JSON body as errors
+    check: bool,
+
+    #[argh(option)]
+    /// base URL of the service under test (overrides TSTIT_URL)
+    url: Option<String>,
+    #[argh(option)]
+    /// value of Authorization header (overrides TSTIT_TKN)
+    token: Option<String>,
+    #[argh(option)]
+    /// path to config file (default: tstit.toml in current or any parent directory)
+    config: Option<PathBuf>,
+    #[argh(option)]
+    /// name of config file profile to use
+    profile: Option<String>,
+    #[argh(switch, short = 'v')]
+    /// enable verbose output
+    verbose: bool,
+}
+
+// per worker: latencies of all the requests and number of errors
+type WorkerStats = (Vec<Duration>, usize);
+
+/// Runs `bench` subcommand, returning process exit code.
+pub fn main(args: &[String]) -> i32 {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let args = match BenchArgs::from_args(&["tstit bench"], &args) {
+        Ok(args) => args,
+        Err(early_exit) => {
+            return match early_exit.status {
+                Ok(_) => {
+                    println!("{}", early_exit.output);
+                    0
+                }
+                Err(_) => {
+                    eprintln!("{}", early_exit.output);
+                    1
+                }
+            };
+        }
+    };
+
+    pretty_env_logger::formatted_builder()
+        .filter_level(if args.verbose {
+            log::LevelFilter::Debug
+        } else {
+            log::LevelFilter::Info
+        })
+        .init();
+
+    match run(&args) {
+        Ok(_) => 0,
+        Err(e) => {
+            error!("bench failed: {}", e);
+            2
+        }
+    }
+}
+
+fn run(args: &BenchArgs) -> Result<(), Box<dyn Error>> {
+    let cli = Layer {
+        base_url: args.url.clone(),
+        token: args.token.clone(),
+        ..Default::default()
+    };
+    let settings = config::resolve(cli, args.config.as_deref(), args.profile.as_deref())?;
+    let plan = TestPlan::load(&args.plan.to_string_lossy())?;
+    let engine = TestEngine::new(plan, &settings, &Vars::default());
+
+    info!(
+        "benchmarking {} for {:?} with {} workers...",
+        args.plan.display(),
+        args.duration,
+        args.concurrency
+    );
+    let started = Instant::now();
+    let deadline = started + args.duration;
+    let stats: Vec<WorkerStats> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..args.concurrency.max(1))
+            .map(|_| scope.spawn(|| worker(&engine, deadline, args.check)))
+            .collect();
+        workers
+            .into_iter()
+            .map(|worker| worker.join().unwrap_or_default())
+            .collect()
+    });
+    let elapsed = started.elapsed();
+
+    let mut latencies: Vec<Duration> = stats.iter().flat_map(|(l, _)| l.iter().copied()).collect();
+    let errors: usize = stats.iter().map(|(_, errors)| errors).sum();
+    latencies.sort();
+    let total = latencies.len();
+    if total == 0 {
+        return Err("no requests were completed".into());
+    }
+
+    info!(
+        "requests: {total}, errors: {errors} ({:.2}%), rps: {:.1}",
+        errors as f64 * 100.0 / total as f64,
+        total as f64 / elapsed.as_secs_f64()
+    );
+    info!(
+        "latency p50: {:?}, p90: {:?}, p99: {:?}, max: {:?}",
+        percentile(&latencies, 50),
+        percentile(&latencies, 90),
+        percentile(&latencies, 99),
+        latencies[total - 1]
+    );
+    Ok(())
+}
+
+// sends requests until deadline, without the regular validation
+fn worker(engine: &TestEngine, deadline: Instant, check: bool) -> WorkerStats {
+    let mut latencies = Vec::new();
+    let mut errors = 0;
+    while Instant::now() < deadline {
+        let started = Instant::now();
+        let ok = engine.probe(check).is_ok();
+        latencies.push(started.elapsed());
+        if !ok {
+            errors += 1;
+        }
+    }
+    (latencies, errors)
+}
+
+fn percentile(sorted: &[Duration], pct: usize) -> Duration {
+    sorted[(sorted.len() * pct / 100).min(sorted.len() - 1)]
+}
+
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(idx) => value.split_at(idx),
+        None => (value, "s"),
+    };
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid duration '{value}'"))?;
+    match unit {
+        "ms" => Ok(Duration::from_millis(number)),
+        "s" => Ok(Duration::from_secs(number)),
+        "m" => Ok(Duration::from_secs(number * 60)),
+        _ => Err(format!(
+            "invalid duration unit in '{value}', ms, s or m expected"
+        )),
+    }
+}
@@ -0,0 +1,60 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs,
+    hash::{DefaultHasher, Hash, Hasher},
+    path::Path,
+};
+
+/// Fingerprints of the last passed responses, keyed by testplan path.
+pub const CACHE_FILE: &str = ".tstit/cache.json";
+
+#[derive(Debug, Default)]
+pub struct Cache {
+    fingerprints: HashMap<String, u64>,
+}
+
+impl Cache {
+    // missing or broken cache is just empty
+    pub fn load() -> Self {
+        let fingerprints = fs::read_to_string(CACHE_FILE)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+        Cache { fingerprints }
+    }
+
+    pub fn get(&self, key: &str) -> Option<u64> {
+        self.fingerprints.get(key).copied()
+    }
+
+    pub fn insert(&mut self, key: String, fingerprint: u64) {
+        self.fingerprints.insert(key, fingerprint);
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        if let Some(dir) = Path::new(CACHE_FILE).parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(
+            CACHE_FILE,
+            serde_json::to_string_pretty(&self.fingerprints)?,
+        )?;
+        Ok(())
+    }
+}
+
+/// Hash of the testplan source together with the response body, so any change
+/// of the testplan file invalidates its cached fingerprint.
+pub fn fingerprint(source_hash: u64, body: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    source_hash.hash(&mut hasher);
+    body.hash(&mut hasher);
+    hasher.finish()
+}
+
+pub fn hash_source(source: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    source.hash(&mut hasher);
+    hasher.finish()
+}
@@ -0,0 +1,193 @@
+use log::info;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    env,
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+pub const CONFIG_FILE: &str = "tstit.toml";
+
+/// Effective run settings, resolved from all layers.
+#[derive(Debug, Clone, Default)]
+pub struct Settings {
+    pub url: Option<String>,
+    pub base_path: Option<String>,
+    pub token: Option<String>,
+    pub timeout: Option<u64>,
+    // report failing fields as JSON Pointers (`/data/items/0/id`)
+    pub json_pointer_errors: bool,
+    // skip validation of responses, which are identical to the last passed ones
+    pub skip_unchanged: bool,
+    // print effective testplan before its execution
+    pub explain: bool,
+    // vars of `--env-file`, process env takes precedence unless `env_file_override`
+    pub env_file: HashMap<String, String>,
+    pub env_file_override: bool,
+    // colored diffs of failures in the log
+    pub color: bool,
+    // seed of generated vars, random per run otherwise
+    pub seed: Option<u64>,
+}
+
+/// One layer of settings: CLI, env vars, config profile or config defaults.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct Layer {
+    pub base_url: Option<String>,
+    pub base_path: Option<String>,
+    pub token: Option<String>,
+    // shell command, whose stdout is used as a token
+    pub token_command: Option<String>,
+    // request timeout, in seconds
+    pub timeout: Option<u64>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct Config {
+    #[serde(flatten)]
+    pub defaults: Layer,
+    #[serde(default)]
+    pub profiles: HashMap<String, Layer>,
+}
+
+impl Config {
+    /// Looks for `tstit.toml` in `start` directory and all its parents.
+    pub fn discover(start: &Path) -> Option<PathBuf> {
+        start
+            .ancestors()
+            .map(|dir| dir.join(CONFIG_FILE))
+            .find(|path| path.is_file())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let content = fs::read_to_string(path)?;
+        Ok(toml::from_str(&content)?)
+    }
+
+    /// Returns defaults, overridden by the given profile (if any).
+    pub fn layer(&self, profile: Option<&str>) -> Result<Layer, Box<dyn Error>> {
+        match profile {
+            Some(name) => match self.profiles.get(name) {
+                Some(profile) => Ok(profile.clone().over(self.defaults.clone())),
+                None => Err(format!("profile '{name}' is not found in config").into()),
+            },
+            None => Ok(self.defaults.clone()),
+        }
+    }
+}
+
+/// Resolves settings with precedence:
+/// CLI > env vars > config file (profile > defaults) > built-in defaults.
+/// Config file is either given explicitly, or discovered from the current directory.
+pub fn resolve(
+    cli: Layer,
+    config_path: Option<&Path>,
+    profile: Option<&str>,
+) -> Result<Settings, Box<dyn Error>> {
+    let config_path = match config_path {
+        Some(path) => Some(path.to_path_buf()),
+        None => Config::discover(&env::current_dir()?),
+    };
+    let config = match config_path {
+        Some(path) => {
+            info!("using {} config", path.display());
+            Config::load(&path)?
+        }
+        None => Config::default(),
+    };
+
+    cli.over(Layer::from_env()?)
+        .over(config.layer(profile)?)
+        .resolve()
+}
+
+impl Layer {
+    pub fn from_env() -> Result<Self, Box<dyn Error>> {
+        Ok(Layer {
+            base_url: env::var("TSTIT_URL").ok(),
+            base_path: env::var("TSTIT_BASE_PATH").ok(),
+            token: env::var("TSTIT_TKN").ok(),
+            token_command: None,
+            timeout: env::var("TSTIT_TIMEOUT")
+                .ok()
+                .map(|timeout| timeout.parse())
+                .transpose()?,
+        })
+    }
+
+    /// Merges `self` on top of `lower`: values set in `self` win.
+    pub fn over(self, lower: Layer) -> Layer {
+        // token and token_command come in pair from the same layer
+        let (token, token_command) = if self.token.is_some() || self.token_command.is_some() {
+            (self.token, self.token_command)
+        } else {
+            (lower.token, lower.token_command)
+        };
+        Layer {
+            base_url: self.base_url.or(lower.base_url),
+            base_path: self.base_path.or(lower.base_path),
+            token,
+            token_command,
+            timeout: self.timeout.or(lower.timeout),
+        }
+    }
+
+    pub fn resolve(self) -> Result<Settings, Box<dyn Error>> {
+        let token = match (self.token, self.token_command) {
+            (Some(token), _) => Some(token),
+            (None, Some(command)) => Some(run_token_command(&command)?),
+            (None, None) => None,
+        };
+        Ok(Settings {
+            url: self.base_url,
+            base_path: self.base_path,
+            token,
+            timeout: self.timeout,
+            ..Default::default()
+        })
+    }
+}
+
+/// Reads `KEY=VALUE` lines of `.env` file, skipping blank lines and `#` comments.
+/// Optional `export ` prefix and quotes around the value are stripped.
+pub fn load_env_file(path: &Path) -> Result<HashMap<String, String>, Box<dyn Error>> {
+    let content = fs::read_to_string(path)?;
+    let mut vars = HashMap::new();
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let Some((key, value)) = line.split_once('=') else {
+            return Err(format!("{}:{}: expected KEY=VALUE", path.display(), index + 1).into());
+        };
+        let value = value.trim();
+        let value = ['"', '\'']
+            .iter()
+            .find_map(|quote| {
+                value
+                    .strip_prefix(*quote)
+                    .and_then(|value| value.strip_suffix(*quote))
+            })
+            .unwrap_or(value);
+        vars.insert(key.trim().to_string(), value.to_string());
+    }
+    Ok(vars)
+}
+
+fn run_token_command(command: &str) -> Result<String, Box<dyn Error>> {
+    let output = Command::new("sh").arg("-c").arg(command).output()?;
+    if !output.status.success() {
+        return Err(format!(
+            "token command failed with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+    Ok(String::from_utf8(output.stdout)?.trim().to_string())
+}
@@ -1,99 +1,666 @@
-use log::{debug, error, info};
+use chrono::{SecondsFormat, Utc};
+use indexmap::IndexMap;
+use log::{Level, debug, error, info, log_enabled, warn};
+use rand::{Rng, SeedableRng, rngs::StdRng};
 use regex::Regex;
+use reqwest::{
+    Method, Proxy,
+    blocking::{Client, multipart},
+    redirect::Policy,
+};
 use serde_json::Value;
+use similar::{ChangeTag, TextDiff};
 use std::{
-    collections::HashMap,
+    borrow::Cow,
+    collections::{HashMap, HashSet},
     env,
     error::Error,
-    process::{Command, Output},
+    fmt, fs,
+    hash::{DefaultHasher, Hash, Hasher},
+    io::{BufReader, ErrorKind, Write},
+    mem,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    process::{Command, Output, Stdio},
+    sync::{Arc, LazyLock, Mutex, PoisonError, RwLock},
+    thread,
+    time::{Duration, Instant},
 };
 
-use crate::plan::TestPlan;
+use crate::{
+    cache::fingerprint,
+    config::Settings,
+    plan::{Auth, Step, TestPlan, var_value},
+    report::{Assertion, mask},
+    response::{Response, mask_header},
+    stream::{self, Keep},
+};
 
 #[derive(Debug, thiserror::Error)]
 pub enum EngineError {
     #[error("command execution failed: {0}")]
     ExecutionFailed(String),
     #[error("API error {0}: {1}")]
-    ApiError(i64, String),
+    ApiError(String, String),
     #[error("validation failed - missing field: {0}")]
     MissingField(String),
     #[error("validation failed - field mismatch: {0}")]
     FieldMismatch(String),
     #[error("invalid response: {0}")]
     InvalidResponse(String),
+    #[error("request timed out after {0} ms")]
+    Timeout(u128),
+    #[error("response time violation: took {1} ms, expected {0} {2} ms")]
+    ResponseTime(Bound, u128, u64),
+    #[error("validation failed - headers mismatch:\n{0}")]
+    HeaderMismatch(String),
+    #[error("validation failed - response is not idempotent:\n{0}")]
+    NotIdempotent(String),
+    #[error("validation failed - body mismatch (expected != actual):\n{0}")]
+    BodyMismatch(String),
+    #[error("invalid testplan: {0}")]
+    InvalidPlan(String),
+    #[error("validation failed - schema violation:\n{0}")]
+    SchemaViolation(String),
+    #[error("validation failed - HTTP status expected {0}, but got {1}")]
+    StatusMismatch(String, u16),
+    #[error("failed to parse JSON response ({source}), got: {snippet}")]
+    JsonParse {
+        snippet: String,
+        source: serde_json::Error,
+    },
+    #[error("step {0} failed: {1}")]
+    StepFailed(usize, String),
+    #[error("setup failed: {0}")]
+    SetupFailed(String),
+    #[error("teardown failed: {0}")]
+    TeardownFailed(String),
     #[error("failed to parse integer: {0}")]
     ParseIntError(#[from] std::num::ParseIntError),
 }
 
+/// Bound of the response time, violated by the response.
+#[derive(Debug)]
+pub enum Bound {
+    Min,
+    Max,
+}
+
+impl fmt::Display for Bound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Bound::Min => write!(f, "at least"),
+            Bound::Max => write!(f, "at most"),
+        }
+    }
+}
+
+/// Vars, assigned from responses, shared by all the testplans of the run.
+pub type Vars = Arc<RwLock<HashMap<String, String>>>;
+
 #[derive(Debug)]
 pub struct TestEngine {
     plan: TestPlan,
-    env_vars: HashMap<String, String>,
+    settings: Settings,
+    env_vars: Vars,
+    // fingerprint of the last passed response, from cache
+    cached_fingerprint: Option<u64>,
+    fingerprint: Option<u64>,
+    // request of the last send, with vars substituted, i.e. for reports
+    last_request: Option<Request>,
+    last_response: Option<Response>,
+    assertions: Vec<Assertion>,
+    // cookie jar of the testplan, shared by its steps and hooks
+    cookies: IndexMap<String, String>,
+    // generator of `$UUID` and `$RANDOM_INT`, seeded per testplan, so concurrent runs of
+    // the same seed are reproducible too; shared by the workers of `bench`
+    rng: Mutex<StdRng>,
 }
 
 impl TestEngine {
-    pub fn new(plan: TestPlan) -> Self {
+    pub fn new(plan: TestPlan, settings: &Settings, vars: &Vars) -> Self {
+        let rng = match settings.seed {
+            Some(seed) => {
+                let mut hasher = DefaultHasher::new();
+                (seed, &plan.name, &plan.row).hash(&mut hasher);
+                StdRng::seed_from_u64(hasher.finish())
+            }
+            None => StdRng::from_entropy(),
+        };
         TestEngine {
             plan,
-            env_vars: HashMap::new(),
+            settings: settings.clone(),
+            env_vars: Arc::clone(vars),
+            cached_fingerprint: None,
+            fingerprint: None,
+            last_request: None,
+            last_response: None,
+            assertions: Vec::new(),
+            cookies: IndexMap::new(),
+            rng: Mutex::new(rng),
         }
     }
 
+    // results of all the checked fields, both passed and failed
+    pub fn assertions(&self) -> &[Assertion] {
+        &self.assertions
+    }
+
+    pub fn last_response(&self) -> Option<&Response> {
+        self.last_response.as_ref()
+    }
+
+    pub fn set_cached_fingerprint(&mut self, fingerprint: Option<u64>) {
+        self.cached_fingerprint = fingerprint;
+    }
+
+    // fingerprint of the executed response, for caching
+    pub fn fingerprint(&self) -> Option<u64> {
+        self.fingerprint
+    }
+
     pub fn execute(&mut self) -> Result<(), Box<dyn Error>> {
-        let executor = self.plan.plan.executor.as_str();
-        debug!("using {executor} executor");
+        if let Some(setup) = self.plan.setup.clone() {
+            info!("executing setup...");
+            self.execute_hook(setup)
+                .map_err(|e| EngineError::SetupFailed(e.to_string()))?;
+        }
 
-        let output = match executor {
-            "curl" | "" => self.execute_curl()?,
-            _ => {
-                return Err(Box::new(EngineError::ExecutionFailed(format!(
-                    "unsupported {executor} executor"
-                ))));
+        let outcome = self.execute_main();
+
+        if let Some(teardown) = self.plan.teardown.clone() {
+            info!("executing teardown...");
+            if let Err(e) = self.execute_hook(teardown) {
+                let e = EngineError::TeardownFailed(e.to_string());
+                // failure of the main request(s) is the one reported, teardown one is just logged
+                match outcome {
+                    Ok(_) => return Err(Box::new(e)),
+                    Err(_) => error!("{e}"),
+                }
             }
+        }
+        outcome
+    }
+
+    /// Method and URL of the request (of the last executed step), i.e. for reports.
+    pub fn request_line(&self) -> String {
+        match &self.last_request {
+            Some(request) => format!("{} {}", request.method, request.url),
+            None => format!(
+                "{} {}",
+                self.plan.input.method.as_deref().unwrap_or("GET"),
+                self.plan.input.url
+            ),
+        }
+    }
+
+    /// Evaluates `skip_if` condition of the testplan, returns the reason of skipping it.
+    pub fn skip_reason(&self) -> Result<Option<String>, Box<dyn Error>> {
+        let Some(condition) = &self.plan.plan.skip_if else {
+            return Ok(None);
+        };
+        let (left, right, equal) = match condition.split_once("!=") {
+            Some((left, right)) => (left, right, false),
+            None => match condition.split_once("==") {
+                Some((left, right)) => (left, right, true),
+                None => {
+                    return Err(Box::new(EngineError::InvalidPlan(format!(
+                        "skip_if {condition} is neither `==` nor `!=` comparison"
+                    ))));
+                }
+            },
         };
+        let left = self.substitute_env_vars(left.trim())?;
+        let right = self.substitute_env_vars(right.trim())?;
+        Ok(((left == right) == equal).then(|| format!("skip_if {condition} holds")))
+    }
+
+    // runs setup or teardown request, keeping the main one (and its results) intact
+    fn execute_hook(&mut self, hook: Step) -> Result<(), Box<dyn Error>> {
+        let input = mem::replace(&mut self.plan.input, hook.input);
+        let output = mem::replace(&mut self.plan.output, hook.output);
+        let cached_fingerprint = self.cached_fingerprint.take();
+        let fingerprint = self.fingerprint.take();
+        let last_request = self.last_request.take();
+        let last_response = self.last_response.take();
+
+        let outcome = self.execute_request();
+
+        self.plan.input = input;
+        self.plan.output = output;
+        self.cached_fingerprint = cached_fingerprint;
+        self.fingerprint = fingerprint;
+        // request and response of the failed hook are kept for reports
+        if outcome.is_ok() {
+            self.last_request = last_request;
+            self.last_response = last_response;
+        }
+        outcome
+    }
+
+    fn execute_main(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.plan.steps.is_empty() {
+            return self.execute_request();
+        }
+
+        // steps share the vars, so values assigned by one step are available to the next ones;
+        // unchanged responses are not skipped, as the cache keeps a single fingerprint per testplan
+        self.cached_fingerprint = None;
+        for (index, step) in self.plan.steps.clone().into_iter().enumerate() {
+            info!("executing step {}...", index + 1);
+            self.plan.input = step.input;
+            self.plan.output = step.output;
+            self.execute_request()
+                .map_err(|e| EngineError::StepFailed(index + 1, e.to_string()))?;
+        }
+        Ok(())
+    }
+
+    fn execute_request(&mut self) -> Result<(), Box<dyn Error>> {
+        // vars are substituted once, so retries (and the repeated request) send the same values
+        let request = self.resolve_request();
+        self.last_request = request.as_ref().ok().cloned();
+        // stdout is kept for `--format tap/json` reports
+        if self.settings.explain {
+            eprintln!("{self}");
+        }
+        let request = request?;
+        let (response, elapsed_ms) = self.send_with_retries(&request)?;
+        debug!("response received in {elapsed_ms} ms");
+        debug!("raw response: {}", mask(&response.body, &self.secrets()));
+        self.last_response = Some(response.clone());
+        if self.plan.plan.cookies {
+            self.store_cookies(&response);
+        }
 
-        let response = String::from_utf8(output.stdout.clone())?;
-        debug!("raw response: {}", response);
-        let json: Value = serde_json::from_str(&response)?;
+        self.validate_status(&response)?;
+        if self.bodyless() && response.body.trim().is_empty() {
+            return self.validate_bodyless(&response, elapsed_ms);
+        }
+        self.validate_command_output(&response)?;
+        let json = parse_json(&response.body)?;
 
-        self.validate_command_output(&output)?;
+        let fingerprint = fingerprint(self.plan.source_hash, &response.body);
+        self.fingerprint = Some(fingerprint);
+        if self.cached_fingerprint == Some(fingerprint) {
+            info!("response is unchanged since the last pass, skipping validation");
+            self.assign_output(&response, &json, elapsed_ms)?;
+            self.save_response(&response)?;
+            return Ok(());
+        }
+
+        self.validate_response_time(elapsed_ms)?;
+        self.validate_headers(&response)?;
+        self.validate_body(&json)?;
+        self.validate_schemas(&response, &json)?;
         self.validate_output(&json)?;
-        self.assign_output(&json)?;
+        if self.plan.plan.assert_idempotent {
+            self.validate_idempotency(&request, &json)?;
+        }
+        self.assign_output(&response, &json, elapsed_ms)?;
+        self.save_response(&response)?;
         Ok(())
     }
 
-    fn execute_curl(&self) -> Result<Output, Box<dyn Error>> {
-        let mut cmd = Command::new(self.plan.plan.executor.clone());
-        let mut cmd = cmd
-            .arg("-sS")
-            .arg("-X")
-            .arg(self.plan.input.method.as_deref().unwrap_or_default())
-            .arg("-d")
-            .arg(self.substitute_env_vars(self.plan.input.json.as_deref().unwrap_or(""))?)
-            .arg("-H")
-            .arg("Content-Type:application/json");
+    /// Sends the request once, without validation, but optional lightweight check of the body.
+    pub fn probe(&self, check: bool) -> Result<u16, Box<dyn Error>> {
+        let response = self.send(&self.resolve_request()?)?;
+        if check {
+            self.validate_command_output(&response)?;
+            parse_json(&response.body)?;
+        }
+        Ok(response.status)
+    }
+
+    // response time is of the last attempt
+    fn send_with_retries(&self, request: &Request) -> Result<(Response, u128), Box<dyn Error>> {
+        let retries = self.plan.plan.retries.unwrap_or_default();
+        let delay = Duration::from_millis(self.plan.plan.retry_delay_ms.unwrap_or_default());
+        let mut attempt = 0;
+        loop {
+            let started = Instant::now();
+            let result = self.send(request);
+            let elapsed_ms = started.elapsed().as_millis();
+            let transient = match &result {
+                Ok(response) => self.plan.plan.retry_statuses.contains(&response.status),
+                Err(e) => matches!(
+                    e.downcast_ref::<EngineError>(),
+                    Some(EngineError::ExecutionFailed(_) | EngineError::Timeout(_))
+                ),
+            };
+            if !transient || attempt >= retries {
+                return result.map(|response| (response, elapsed_ms));
+            }
+            attempt += 1;
+            match &result {
+                Ok(response) => {
+                    debug!(
+                        "got {} status, retry {attempt} of {retries}",
+                        response.status
+                    )
+                }
+                Err(e) => debug!("request failed ({e}), retry {attempt} of {retries}"),
+            }
+            thread::sleep(delay);
+        }
+    }
+
+    fn send(&self, request: &Request) -> Result<Response, Box<dyn Error>> {
+        let executor = self.plan.plan.executor.as_str();
+        debug!("using {executor} executor");
+        self.log_request(request);
+
+        match executor {
+            "curl" | "" => {
+                if self.plan.plan.stream {
+                    warn!("streaming is supported by http executor only, ignoring");
+                }
+                let output = self.execute_curl(request)?;
+                Ok(Response::parse(&String::from_utf8(output.stdout)?))
+            }
+            "http" => self.execute_http(request),
+            _ => Err(Box::new(EngineError::ExecutionFailed(format!(
+                "unsupported {executor} executor"
+            )))),
+        }
+    }
+
+    // native executor, without spawning curl per request
+    fn execute_http(&self, request: &Request) -> Result<Response, Box<dyn Error>> {
+        let client = shared_client(ClientOptions {
+            max_redirects: self.max_redirects(),
+            insecure: self.insecure(),
+            proxy: self.proxy()?,
+            resolve: self.resolve()?,
+        })?;
 
-        cmd = if let Ok(token) = env::var("TSTIT_TKN") {
-            cmd.arg("-H").arg(format!("Authorization:{}", token))
+        if self.plan.plan.dns_servers.is_some() || self.plan.plan.doh_url.is_some() {
+            warn!("resolver overrides are supported by curl executor only, ignoring");
+        }
+        if self.plan.plan.curl_args.is_some() {
+            warn!("curl_args are supported by curl executor only, ignoring");
+        }
+
+        let Request {
+            method,
+            url,
+            credentials,
+            headers,
+            body,
+            ..
+        } = request;
+        let mut builder = client.request(Method::from_bytes(method.as_bytes())?, url);
+        match body {
+            Some(Body::Multipart(fields, files)) => {
+                let mut form = multipart::Form::new();
+                for (name, value) in fields {
+                    form = form.text(name.clone(), value.clone());
+                }
+                for (name, path) in files {
+                    form = form.file(name.clone(), path)?;
+                }
+                builder = builder.multipart(form);
+            }
+            Some(Body::Raw(body)) => {
+                builder = builder.body(body.clone());
+                if !self.has_input_header("Content-Type") {
+                    builder = builder.header("Content-Type", self.content_type());
+                }
+            }
+            None => {}
+        }
+        builder = match credentials {
+            Some(Credentials::Header(name, value)) => builder.header(name, value),
+            Some(Credentials::Basic(username, password)) => {
+                builder.basic_auth(username, Some(password))
+            }
+            None => builder,
+        };
+        if let Some(cookies) = self.cookie_header() {
+            builder = builder.header("Cookie", cookies);
+        }
+        for (name, value) in headers {
+            builder = builder.header(name, value);
+        }
+        let timeout = self.timeout();
+        if let Some(timeout) = timeout {
+            builder = builder.timeout(timeout);
+        }
+
+        debug!("sending {method} {url}");
+        let response = builder.send().map_err(|e| match timeout {
+            Some(timeout) if e.is_timeout() => EngineError::Timeout(timeout.as_millis()),
+            _ => EngineError::ExecutionFailed(e.to_string()),
+        })?;
+        debug!("received {}", response.status());
+
+        let status = response.status().as_u16();
+        let headers = response
+            .headers()
+            .iter()
+            .map(|(name, value)| {
+                (
+                    name.as_str().to_string(),
+                    value.to_str().unwrap_or_default().to_string(),
+                )
+            })
+            .collect();
+        // small responses (of known length) are not worth streaming
+        let body = if self.plan.plan.stream
+            && response.status().is_success()
+            && response
+                .content_length()
+                .is_none_or(|length| length >= STREAM_MIN_BYTES)
+        {
+            let json = stream::parse(BufReader::new(response), &self.kept_fields()?)?;
+            debug!("streamed response is pruned to the expected fields");
+            json.to_string()
         } else {
-            cmd
+            response.text()?
         };
+        Ok(Response {
+            status,
+            headers,
+            body,
+        })
+    }
 
-        let url = format!(
-            "{}{}",
-            env::var("TSTIT_URL").map_err(|_| "TSTIT_URL env var is not set!")?,
-            self.substitute_env_vars(&self.plan.input.url)?
+    // outgoing request line by line, with secrets redacted, i.e. to debug failed requests
+    fn log_request(&self, request: &Request) {
+        if !log_enabled!(Level::Debug) {
+            return;
+        }
+        let secrets = &request.secrets;
+        debug!(
+            "request: {} {}",
+            request.method,
+            mask(&request.url, secrets)
         );
-        let cmd = cmd.arg(url);
+        match &request.credentials {
+            Some(Credentials::Header(name, _)) => debug!("request header: {name}: ***"),
+            Some(Credentials::Basic(..)) => debug!("request header: Authorization: Basic ***"),
+            None => {}
+        }
+        if self.cookie_header().is_some() {
+            debug!("request header: Cookie: ***");
+        }
+        if let Some(Body::Raw(_)) = request.body
+            && !self.has_input_header("Content-Type")
+        {
+            debug!("request header: Content-Type: {}", self.content_type());
+        }
+        for (name, value) in &request.headers {
+            debug!(
+                "request header: {name}: {}",
+                mask(mask_header(name, value), secrets)
+            );
+        }
+        match &request.body {
+            Some(Body::Raw(body)) => debug!("request body: {}", mask(body, secrets)),
+            Some(Body::Multipart(fields, files)) => {
+                for (name, value) in fields {
+                    debug!("request field: {name} = {}", mask(value, secrets));
+                }
+                for (name, path) in files {
+                    debug!("request file: {name} = {}", path.display());
+                }
+            }
+            None => {}
+        }
+    }
+
+    /// Values, which are never logged as is: of the last request, or the token before it's sent.
+    pub fn secrets(&self) -> Vec<String> {
+        match &self.last_request {
+            Some(request) => request.secrets.clone(),
+            None => self.settings.token.iter().cloned().collect(),
+        }
+    }
+
+    // fields of the response, validated or assigned, for streaming to keep
+    fn kept_fields(&self) -> Result<Keep, Box<dyn Error>> {
+        let output = &self.plan.output;
+        let whole_body = [
+            (
+                "expect_body",
+                output.expect_body.is_some() || output.expect_body_env.is_some(),
+            ),
+            (
+                "schema",
+                output.schema.is_some() || !output.schemas.is_empty(),
+            ),
+            ("save_to", output.save_to.is_some()),
+            ("assert_idempotent", self.plan.plan.assert_idempotent),
+        ];
+        if let Some((option, _)) = whole_body.iter().find(|(_, used)| *used) {
+            return Err(Box::new(EngineError::InvalidPlan(format!(
+                "{option} needs the whole response, so it's not supported with stream = true"
+            ))));
+        }
 
-        debug!("executing command: {:?}", cmd);
+        let plan = &self.plan.plan;
+        let mut keep = Keep::fields();
+        if plan.envelope {
+            keep.insert(&plan.code_field);
+        }
+        for key in output.expect.keys() {
+            let key = from_json_path(key);
+            keep.insert(&key);
+            if plan.envelope {
+                keep.insert(&format!("{}.{key}", plan.data_field));
+            }
+        }
+        for key in output.assign.iter().flat_map(IndexMap::keys) {
+            if !key.starts_with('@') && !key.starts_with("header:") {
+                keep.insert(&from_json_path(key));
+            }
+        }
+        Ok(keep)
+    }
+
+    fn execute_curl(&self, request: &Request) -> Result<Output, Box<dyn Error>> {
+        let mut cmd = Command::new("curl");
+        let method = request.method.as_str();
+        let mut cmd = cmd.arg("-sS").arg("-i");
+        // `-X HEAD` would wait for the body, which never comes
+        cmd = if method.eq_ignore_ascii_case("HEAD") {
+            cmd.arg("-I")
+        } else {
+            cmd.arg("-X").arg(method)
+        };
+
+        match &request.body {
+            // multipart Content-Type (with its boundary) is set by curl itself
+            Some(Body::Multipart(fields, files)) => {
+                for (name, path) in files {
+                    cmd = cmd.arg("-F").arg(format!("{name}=@{}", path.display()));
+                }
+                for (name, value) in fields {
+                    cmd = cmd.arg("--form-string").arg(format!("{name}={value}"));
+                }
+            }
+            Some(Body::Raw(body)) => {
+                cmd = cmd.arg("-d").arg(body);
+                // explicit Content-Type of the testplan overrides the default one
+                if !self.has_input_header("Content-Type") {
+                    cmd = cmd
+                        .arg("-H")
+                        .arg(format!("Content-Type:{}", self.content_type()));
+                }
+            }
+            None => {}
+        }
+
+        cmd = match &request.credentials {
+            Some(Credentials::Header(name, value)) => cmd.arg("-H").arg(format!("{name}:{value}")),
+            Some(Credentials::Basic(username, password)) => {
+                cmd.arg("-u").arg(format!("{username}:{password}"))
+            }
+            None => cmd,
+        };
+
+        if let Some(cookies) = self.cookie_header() {
+            cmd = cmd.arg("-b").arg(cookies);
+        }
+
+        for (name, value) in &request.headers {
+            cmd = cmd.arg("-H").arg(format!("{name}: {value}"));
+        }
+
+        if let Some(dns_servers) = &self.plan.plan.dns_servers {
+            cmd = cmd
+                .arg("--dns-servers")
+                .arg(self.substitute_env_vars(dns_servers)?);
+        }
+        if let Some(doh_url) = &self.plan.plan.doh_url {
+            cmd = cmd.arg("--doh-url").arg(self.substitute_env_vars(doh_url)?);
+        }
+        for entry in &self.plan.plan.resolve {
+            cmd = cmd.arg("--resolve").arg(self.substitute_env_vars(entry)?);
+        }
+
+        if let Some(max_redirects) = self.max_redirects() {
+            cmd = cmd
+                .arg("-L")
+                .arg("--max-redirs")
+                .arg(max_redirects.to_string());
+        }
+        if self.insecure() {
+            cmd = cmd.arg("-k");
+        }
+        if let Some(proxy) = self.proxy()? {
+            cmd = cmd.arg("-x").arg(proxy);
+        }
+
+        let timeout = self.timeout();
+        cmd = if let Some(timeout) = timeout {
+            cmd.arg("--max-time")
+                .arg(format!("{:.3}", timeout.as_secs_f64()))
+        } else {
+            cmd
+        };
+
+        // escape hatch for the flags, not modeled by the testplan
+        if let Some(curl_args) = &self.plan.plan.curl_args {
+            cmd = cmd.args(curl_args);
+        }
+
+        let cmd = cmd.arg(&request.url);
+
+        debug!(
+            "executing command: {}",
+            mask(&format!("{cmd:?}"), &request.secrets)
+        );
         let output = cmd.output()?;
-        debug!("output: {output:?}");
+        debug!("output: {}", mask(&format!("{output:?}"), &request.secrets));
 
         if !output.status.success() {
             let stderr = String::from_utf8_lossy(&output.stderr);
             error!("command failed: {}", stderr);
+            // curl exits with 28 on --max-time expiry
+            if let (Some(timeout), Some(28)) = (timeout, output.status.code()) {
+                return Err(Box::new(EngineError::Timeout(timeout.as_millis())));
+            }
             return Err(Box::new(EngineError::ExecutionFailed(format!(
                 "command failed with status: {}",
                 output.status
@@ -104,8 +671,398 @@ impl TestEngine {
         Ok(output)
     }
 
-    fn validate_command_output(&self, output: &Output) -> Result<(), Box<dyn Error>> {
-        if output.stdout.is_empty() {
+    // request of the testplan with vars substituted, each generated var (`$UUID`) is drawn once
+    fn resolve_request(&self) -> Result<Request, Box<dyn Error>> {
+        let input = &self.plan.input;
+        let mut headers = Vec::with_capacity(input.headers.len());
+        for (name, value) in &input.headers {
+            headers.push((
+                self.substitute_env_vars(name)?,
+                self.substitute_env_vars(value)?,
+            ));
+        }
+        let body = if !self.sends_body() {
+            None
+        } else if input.files.is_empty() {
+            Some(Body::Raw(self.request_body()?))
+        } else {
+            let mut fields = Vec::with_capacity(input.form.len());
+            for (name, value) in &input.form {
+                fields.push((
+                    self.substitute_env_vars(name)?,
+                    self.substitute_env_vars(value)?,
+                ));
+            }
+            Some(Body::Multipart(fields, self.upload_files()?))
+        };
+        let credentials = self.credentials()?;
+        Ok(Request {
+            method: input.method.as_deref().unwrap_or("GET").to_string(),
+            url: self.build_url()?,
+            secrets: self.request_secrets(&credentials, &headers),
+            credentials,
+            headers,
+            body,
+        })
+    }
+
+    // request of `--dry-run`, where vars, which are not assigned yet, are kept as is
+    fn unresolved_request(&self) -> Request {
+        let resolve = |text: &str| {
+            self.substitute_env_vars(text)
+                .unwrap_or_else(|_| text.to_string())
+        };
+        let input = &self.plan.input;
+        let url = self.build_url().unwrap_or_else(|_| {
+            format!(
+                "{}{}{}",
+                self.settings.url.as_deref().unwrap_or("<base URL>"),
+                self.settings
+                    .base_path
+                    .as_deref()
+                    .map(resolve)
+                    .unwrap_or_default(),
+                resolve(&input.url)
+            )
+        });
+        let headers: Vec<_> = input
+            .headers
+            .iter()
+            .map(|(name, value)| (resolve(name), resolve(value)))
+            .collect();
+        let body = if !self.sends_body() {
+            None
+        } else if input.files.is_empty() {
+            let body = self
+                .request_body()
+                .unwrap_or_else(|_| input.json.as_deref().map(resolve).unwrap_or_default());
+            Some(Body::Raw(body))
+        } else {
+            let fields = input
+                .form
+                .iter()
+                .map(|(name, value)| (resolve(name), resolve(value)))
+                .collect();
+            let files = input
+                .files
+                .iter()
+                .map(|(name, path)| {
+                    (
+                        resolve(name),
+                        PathBuf::from(resolve(&path.to_string_lossy())),
+                    )
+                })
+                .collect();
+            Some(Body::Multipart(fields, files))
+        };
+        let credentials = self.credentials().ok().flatten();
+        Request {
+            method: input.method.as_deref().unwrap_or("GET").to_string(),
+            url,
+            secrets: self.request_secrets(&credentials, &headers),
+            credentials,
+            headers,
+            body,
+        }
+    }
+
+    // the token, credentials and sensitive headers of the request
+    fn request_secrets(
+        &self,
+        credentials: &Option<Credentials>,
+        headers: &[(String, String)],
+    ) -> Vec<String> {
+        let mut secrets: Vec<String> = self.settings.token.iter().cloned().collect();
+        match credentials {
+            Some(Credentials::Header(_, value)) => {
+                // bearer token is masked without its scheme as well
+                secrets.extend(value.strip_prefix("Bearer ").map(str::to_string));
+                secrets.push(value.clone());
+            }
+            Some(Credentials::Basic(_, password)) => secrets.push(password.clone()),
+            None => {}
+        }
+        for (name, value) in headers {
+            if mask_header(name, value) != value {
+                secrets.push(value.clone());
+            }
+        }
+        secrets
+    }
+
+    // credentials of `[auth]` section, or the global token as is otherwise
+    fn credentials(&self) -> Result<Option<Credentials>, Box<dyn Error>> {
+        Ok(match &self.plan.auth {
+            Some(Auth::Basic { username, password }) => Some(Credentials::Basic(
+                self.substitute_env_vars(username)?,
+                self.substitute_env_vars(password)?,
+            )),
+            Some(Auth::Bearer { token, header }) => {
+                let token = match token {
+                    Some(token) => self.substitute_env_vars(token)?,
+                    None => self.settings.token.clone().ok_or_else(|| {
+                        EngineError::InvalidPlan("bearer token is not set".to_string())
+                    })?,
+                };
+                // the scheme is added once, even if the token already has it
+                let token = token.strip_prefix("Bearer ").unwrap_or(&token);
+                let header = self.auth_header(header.as_deref())?;
+                Some(Credentials::Header(header, format!("Bearer {token}")))
+            }
+            Some(Auth::ApiKey { key, header }) => Some(Credentials::Header(
+                self.auth_header(header.as_deref())?,
+                self.substitute_env_vars(key)?,
+            )),
+            None => self
+                .settings
+                .token
+                .clone()
+                .map(|token| Credentials::Header("Authorization".to_string(), token)),
+        })
+    }
+
+    // name of the credentials header, `Authorization` by default
+    fn auth_header(&self, header: Option<&str>) -> Result<String, Box<dyn Error>> {
+        match header {
+            Some(header) => self.substitute_env_vars(header),
+            None => Ok("Authorization".to_string()),
+        }
+    }
+
+    // redirects are followed on explicit opt-in only, as curl does
+    fn max_redirects(&self) -> Option<usize> {
+        const DEFAULT_MAX_REDIRECTS: usize = 10;
+        let plan = &self.plan.plan;
+        plan.follow_redirects
+            .unwrap_or_default()
+            .then(|| plan.max_redirects.unwrap_or(DEFAULT_MAX_REDIRECTS))
+    }
+
+    // TLS verification is skipped on explicit opt-in only, i.e. for self-signed staging servers
+    fn insecure(&self) -> bool {
+        // warned once per testplan of the run, instead of on each of its requests
+        static WARNED: LazyLock<Mutex<HashSet<String>>> = LazyLock::new(Default::default);
+        let insecure = self.plan.plan.insecure.unwrap_or_default();
+        if insecure
+            && WARNED
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .insert(self.plan.name.clone())
+        {
+            warn!(
+                "TLS certificate verification is disabled for {}",
+                self.plan.name
+            );
+        }
+        insecure
+    }
+
+    // explicit proxy of the testplan, otherwise env vars are honored (curl ignores HTTP_PROXY)
+    fn proxy(&self) -> Result<Option<String>, Box<dyn Error>> {
+        self.plan
+            .plan
+            .proxy
+            .as_deref()
+            .map(|proxy| self.substitute_env_vars(proxy))
+            .transpose()
+    }
+
+    // `host:port:address` entries, the address might be IPv6 one in brackets (`[::1]`)
+    fn resolve(&self) -> Result<Vec<(String, SocketAddr)>, Box<dyn Error>> {
+        let mut resolve = Vec::with_capacity(self.plan.plan.resolve.len());
+        for entry in &self.plan.plan.resolve {
+            let entry = self.substitute_env_vars(entry)?;
+            let invalid = || {
+                EngineError::InvalidPlan(format!(
+                    "invalid resolve entry '{entry}', host:port:address expected"
+                ))
+            };
+            let mut parts = entry.splitn(3, ':');
+            let (Some(host), Some(port), Some(address)) =
+                (parts.next(), parts.next(), parts.next())
+            else {
+                return Err(Box::new(invalid()));
+            };
+            let port: u16 = port.parse().map_err(|_| invalid())?;
+            let address: IpAddr = address
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .parse()
+                .map_err(|_| invalid())?;
+            resolve.push((host.to_string(), SocketAddr::new(address, port)));
+        }
+        Ok(resolve)
+    }
+
+    // per-request timeout of the testplan, or the global one
+    fn timeout(&self) -> Option<Duration> {
+        self.plan
+            .input
+            .timeout_ms
+            .map(Duration::from_millis)
+            .or(self.settings.timeout.map(Duration::from_secs))
+    }
+
+    fn has_input_header(&self, name: &str) -> bool {
+        self.plan
+            .input
+            .headers
+            .keys()
+            .any(|header| header.eq_ignore_ascii_case(name))
+    }
+
+    // body (and default Content-Type) is sent only by methods carrying it, if any is given
+    fn bodyless(&self) -> bool {
+        let method = self.plan.input.method.as_deref().unwrap_or("GET");
+        method.eq_ignore_ascii_case("HEAD") || method.eq_ignore_ascii_case("OPTIONS")
+    }
+
+    fn sends_body(&self) -> bool {
+        let method = self.plan.input.method.as_deref().unwrap_or("GET");
+        let input = &self.plan.input;
+        let has_body = input.json.is_some()
+            || input.json_env.is_some()
+            || input.json_file.is_some()
+            || !input.form.is_empty()
+            || input.text.is_some()
+            || !input.files.is_empty();
+        has_body
+            && ["POST", "PUT", "PATCH"]
+                .iter()
+                .any(|body_method| body_method.eq_ignore_ascii_case(method))
+    }
+
+    // files to upload, resolved against the testplan directory,
+    // missing ones fail before the request
+    fn upload_files(&self) -> Result<Vec<(String, PathBuf)>, Box<dyn Error>> {
+        let mut files = Vec::with_capacity(self.plan.input.files.len());
+        for (name, path) in &self.plan.input.files {
+            let path = self
+                .plan
+                .dir
+                .join(self.substitute_env_vars(&path.to_string_lossy())?);
+            if !path.is_file() {
+                return Err(Box::new(EngineError::InvalidPlan(format!(
+                    "file {} to upload as '{name}' is not found",
+                    path.display()
+                ))));
+            }
+            files.push((self.substitute_env_vars(name)?, path));
+        }
+        Ok(files)
+    }
+
+    // default Content-Type of the body, according to its type
+    fn content_type(&self) -> &'static str {
+        match self.plan.input.body_type.as_deref() {
+            Some("form") => "application/x-www-form-urlencoded",
+            Some("text") => "text/plain",
+            _ => "application/json",
+        }
+    }
+
+    fn request_body(&self) -> Result<String, Box<dyn Error>> {
+        let input = &self.plan.input;
+        match input.body_type.as_deref().unwrap_or("json") {
+            "json" => match (&input.json, &input.json_env, &input.json_file) {
+                (Some(json), None, None) if input.literal => Ok(json.clone()),
+                (Some(json), None, None) => self.substitute_env_vars(json),
+                (None, Some(var), None) => {
+                    let json = env_json(var)?;
+                    Ok(json.to_string())
+                }
+                (None, None, Some(path)) => {
+                    let path = self.plan.dir.join(path);
+                    let json = fs::read_to_string(&path)
+                        .map_err(|e| format!("failed to read {}: {e}", path.display()))?;
+                    self.substitute_env_vars(&json)
+                }
+                (None, None, None) => Ok(String::new()),
+                _ => Err(Box::new(EngineError::InvalidPlan(
+                    "only one of 'json', 'json_env' and 'json_file' might be set".to_string(),
+                ))),
+            },
+            // form fields are encoded in declaration order
+            "form" => {
+                let mut pairs = Vec::with_capacity(input.form.len());
+                for (key, value) in &input.form {
+                    pairs.push(format!(
+                        "{}={}",
+                        encode_query_component(&self.substitute_env_vars(key)?),
+                        encode_query_component(&self.substitute_env_vars(value)?)
+                    ));
+                }
+                Ok(pairs.join("&"))
+            }
+            "text" => match &input.text {
+                Some(text) => self.substitute_env_vars(text),
+                None => Ok(String::new()),
+            },
+            body_type => Err(Box::new(EngineError::InvalidPlan(format!(
+                "unsupported {body_type} body_type, expected: json, form, text"
+            )))),
+        }
+    }
+
+    fn build_url(&self) -> Result<String, Box<dyn Error>> {
+        let url = match self.plan.input.literal {
+            true => self.plan.input.url.clone(),
+            false => self.substitute_url(&self.plan.input.url)?,
+        };
+        // absolute URLs are used as is, bypassing both base URL and base path
+        let mut url = if url.starts_with("http://") || url.starts_with("https://") {
+            url
+        } else if let Some(base_url) = &self.plan.plan.base_url {
+            // own base URL of the testplan is used instead of both global base URL and base path
+            let base_url = self.substitute_env_vars(base_url)?;
+            format!("{}{}", base_url.trim_end_matches('/'), url)
+        } else {
+            let base_path = match &self.settings.base_path {
+                Some(base_path) => self.substitute_env_vars(base_path)?,
+                None => String::new(),
+            };
+            format!(
+                "{}{}{}",
+                self.settings.url.as_deref().ok_or(
+                    "base URL is not set, use --url, TSTIT_URL or base_url in tstit.toml!"
+                )?,
+                base_path.trim_end_matches('/'),
+                url
+            )
+        };
+
+        // appended to the query of url itself (if any), percent-encoded
+        for (key, value) in &self.plan.input.query {
+            if !url.contains('?') {
+                url.push('?');
+            } else if !url.ends_with('?') && !url.ends_with('&') {
+                url.push('&');
+            }
+            url.push_str(&encode_query_component(&self.substitute_env_vars(key)?));
+            url.push('=');
+            url.push_str(&encode_query_component(&self.substitute_env_vars(value)?));
+        }
+        Ok(url)
+    }
+
+    // transport-level status, checked before the body, which might be not a JSON on errors
+    fn validate_status(&self, response: &Response) -> Result<(), Box<dyn Error>> {
+        let Some(expected) = &self.plan.output.status else {
+            return Ok(());
+        };
+        let expected = expected.to_string();
+        if status_matches(&expected, response.status) {
+            Ok(())
+        } else {
+            Err(Box::new(EngineError::StatusMismatch(
+                expected,
+                response.status,
+            )))
+        }
+    }
+
+    fn validate_command_output(&self, response: &Response) -> Result<(), Box<dyn Error>> {
+        if response.body.trim().is_empty() {
             return Err(Box::new(EngineError::InvalidResponse(
                 "empty response, is service down?".to_string(),
             )));
@@ -113,146 +1070,755 @@ impl TestEngine {
         Ok(())
     }
 
-    fn validate_output(&self, json: &Value) -> Result<(), Box<dyn Error>> {
-        // validating the mandatory code field
-        match json.get("code").and_then(Value::as_i64) {
-            Some(0) => {}
-            Some(code) => {
-                let error_msg = json
-                    .get("data")
-                    .map(|v| v.to_string())
-                    .unwrap_or_else(|| "unknown error".to_string());
-                return Err(Box::new(EngineError::ApiError(code, error_msg)));
-            }
-            None => {
-                return Err(Box::new(EngineError::MissingField(
-                    "required field 'code' is missing".to_string(),
+    // HEAD (and usually OPTIONS) responses have no body, so only their status and headers count
+    fn validate_bodyless(
+        &mut self,
+        response: &Response,
+        elapsed_ms: u128,
+    ) -> Result<(), Box<dyn Error>> {
+        let output = &self.plan.output;
+        if !output.expect.is_empty()
+            || output.expect_body.is_some()
+            || output.expect_body_env.is_some()
+            || output.schema.is_some()
+        {
+            return Err(Box::new(EngineError::InvalidPlan(
+                "body expectations of the response without body".to_string(),
+            )));
+        }
+        self.validate_response_time(elapsed_ms)?;
+        self.validate_headers(response)?;
+        self.assign_output(response, &Value::Null, elapsed_ms)
+    }
+
+    fn validate_response_time(&self, elapsed_ms: u128) -> Result<(), Box<dyn Error>> {
+        if let Some(min_ms) = self.plan.output.min_response_ms
+            && elapsed_ms < min_ms as u128
+        {
+            return Err(Box::new(EngineError::ResponseTime(
+                Bound::Min,
+                elapsed_ms,
+                min_ms,
+            )));
+        }
+        if let Some(max_ms) = self.plan.output.max_response_ms
+            && elapsed_ms > max_ms as u128
+        {
+            return Err(Box::new(EngineError::ResponseTime(
+                Bound::Max,
+                elapsed_ms,
+                max_ms,
+            )));
+        }
+        Ok(())
+    }
+
+    fn validate_headers(&self, response: &Response) -> Result<(), Box<dyn Error>> {
+        let mut diff = String::new();
+        for (name, expected) in &self.plan.output.headers {
+            let name = self.substitute_env_vars(name)?;
+            let expected = self.substitute_expected(expected)?;
+            let actual = response.header(&name);
+            // values are matched with the same grammar as fields of `[out.expect]`
+            let passed = match &actual {
+                None => expected == "@absent" || expected == "@null_or_absent",
+                Some(_) if expected == "@exists" => true,
+                Some(_) if expected == "@absent" || expected == "@null_or_absent" => false,
+                Some(actual) => self
+                    .compare_values(&header_value(actual), &expected)
+                    .map_err(|e| with_field(e, &name))?,
+            };
+            if !passed {
+                diff.push_str(&format!(
+                    "  - {name}: {}\n  + {name}: {}\n",
+                    mask_header(&name, &expected),
+                    actual
+                        .as_deref()
+                        .map_or("<absent>", |actual| mask_header(&name, actual))
+                ));
+            }
+        }
+
+        if !diff.is_empty() {
+            debug!("received headers:\n{}", response.masked_headers());
+            return Err(Box::new(EngineError::HeaderMismatch(diff)));
+        }
+        Ok(())
+    }
+
+    // repeats the same request and compares both responses structurally
+    fn validate_idempotency(&self, request: &Request, json: &Value) -> Result<(), Box<dyn Error>> {
+        let repeated = self.send(request)?;
+        debug!(
+            "repeated response: {}",
+            mask(&repeated.body, &self.secrets())
+        );
+        let repeated = parse_json(&repeated.body)?;
+
+        let mut diff = Vec::new();
+        diff_values(json, &repeated, "", &mut diff);
+        if !diff.is_empty() {
+            return Err(Box::new(EngineError::NotIdempotent(diff.join("\n"))));
+        }
+        debug!("response is idempotent");
+        Ok(())
+    }
+
+    // whole response must be structurally equal to the expected body
+    fn validate_body(&self, json: &Value) -> Result<(), Box<dyn Error>> {
+        let output = &self.plan.output;
+        let expected: Value = match (&output.expect_body, &output.expect_body_env) {
+            (Some(_), Some(_)) => {
+                return Err(Box::new(EngineError::InvalidPlan(
+                    "both 'expect_body' and 'expect_body_env' are set".to_string(),
                 )));
             }
+            (Some(body), None) => {
+                serde_json::from_str(&self.substitute_env_vars(body)?).map_err(|e| {
+                    EngineError::InvalidPlan(format!("'expect_body' is not a valid JSON: {e}"))
+                })?
+            }
+            (None, Some(var)) => env_json(var)?,
+            (None, None) => return Ok(()),
+        };
+
+        let mut diff = Vec::new();
+        diff_values(&expected, json, "", &mut diff);
+        if !diff.is_empty() {
+            return Err(Box::new(EngineError::BodyMismatch(diff.join("\n"))));
+        }
+        Ok(())
+    }
+
+    // picks the schema by HTTP status, as 2xx and 4xx responses usually differ in shape
+    fn validate_schemas(&self, response: &Response, json: &Value) -> Result<(), Box<dyn Error>> {
+        if let Some(schema) = &self.plan.output.schema {
+            self.validate_schema(schema, json)?;
+        }
+        if self.plan.output.schemas.is_empty() {
+            return Ok(());
+        }
+        match self
+            .plan
+            .output
+            .schemas
+            .iter()
+            .find(|(pattern, _)| status_matches(pattern, response.status))
+        {
+            Some((pattern, schema)) => {
+                debug!(
+                    "validating {} status against {pattern} schema",
+                    response.status
+                );
+                self.validate_schema(schema, json)
+            }
+            None => Err(Box::new(EngineError::SchemaViolation(format!(
+                "no schema for {} status",
+                response.status
+            )))),
         }
+    }
 
-        // special handling for direct data field validation (PATCH case)
-        if let Some(expected_data) = self.plan.output.expect.get("data") {
-            match json.get("data") {
-                Some(actual_data) => {
-                    let expected_data_substituted = self.substitute_env_vars(expected_data)?;
-                    if !self.compare_values(actual_data, &expected_data_substituted)? {
+    fn validate_schema(&self, schema_path: &str, json: &Value) -> Result<(), Box<dyn Error>> {
+        const MAX_VIOLATIONS: usize = 5;
+        let schema_path = self.plan.dir.join(self.substitute_env_vars(schema_path)?);
+        let schema: Value = serde_json::from_str(&fs::read_to_string(&schema_path)?)?;
+        let validator = jsonschema::validator_for(&schema)?;
+
+        let violations: Vec<String> = validator
+            .iter_errors(json)
+            .take(MAX_VIOLATIONS)
+            .map(|error| format!("  {}: {}", error.instance_path, error))
+            .collect();
+        if !violations.is_empty() {
+            return Err(Box::new(EngineError::SchemaViolation(format!(
+                "{} (first {} violations at most):\n{}",
+                schema_path.display(),
+                MAX_VIOLATIONS,
+                violations.join("\n")
+            ))));
+        }
+        Ok(())
+    }
+
+    fn validate_output(&mut self, json: &Value) -> Result<(), Box<dyn Error>> {
+        // envelope field names, `code` and `data` by default
+        let code_field = self.plan.plan.code_field.clone();
+        let data_field = self.plan.plan.data_field.clone();
+
+        // plain REST response is validated from its root, without code and data fields
+        let envelope = self.plan.plan.envelope;
+        if envelope {
+            // error code, expected explicitly (`code = "404"`, `code = "!=0"`), is not an API error
+            let expected_error = match self.plan.output.expect.get(&code_field) {
+                Some(expected) => {
+                    let expected = self.substitute_expected(expected)?;
+                    let success_code = &self.plan.plan.success_code;
+                    (!self.compare_values(success_code, &expected)?).then_some(expected)
+                }
+                None => None,
+            };
+
+            // validating the mandatory code field
+            match (json.get(&code_field), expected_error) {
+                (Some(code), Some(expected)) => {
+                    let passed = self.compare_values(code, &expected)?;
+                    let field = self.field_name(json, "", &code_field);
+                    self.assertions.push(Assertion {
+                        field,
+                        expected: expected.clone(),
+                        actual: Some(code.to_string()),
+                        passed,
+                        soft: false,
+                    });
+                    if !passed {
                         return Err(Box::new(EngineError::FieldMismatch(format!(
-                            "'data' field expected '{}', but got '{}'",
-                            expected_data_substituted, actual_data
+                            "'{code_field}' field expected '{expected}', but got '{code}'"
                         ))));
                     }
-                    if self.plan.output.expect.len() == 1 {
-                        info!("validation successful");
-                        return Ok(());
-                    }
                 }
-                None => {
-                    return Err(Box::new(EngineError::MissingField(
-                        "required field 'data' is missing".to_string(),
-                    )));
+                (Some(code), None) if *code == self.plan.plan.success_code => {}
+                (Some(code), None) => {
+                    let error_msg = json
+                        .get(&data_field)
+                        .map(|v| v.to_string())
+                        .unwrap_or_else(|| "unknown error".to_string());
+                    let code = code
+                        .as_str()
+                        .map_or_else(|| code.to_string(), str::to_string);
+                    return Err(Box::new(EngineError::ApiError(code, error_msg)));
+                }
+                (None, _) => {
+                    return Err(Box::new(EngineError::MissingField(format!(
+                        "required field '{code_field}' is missing"
+                    ))));
+                }
+            }
+
+            // special handling for direct data field validation (PATCH case)
+            if let Some(expected_data) = self.plan.output.expect.get(&data_field) {
+                match json.get(&data_field) {
+                    Some(actual_data) => {
+                        let expected_data_substituted = self.substitute_expected(expected_data)?;
+                        let passed =
+                            self.compare_values(actual_data, &expected_data_substituted)?;
+                        let field = self.field_name(json, "", &data_field);
+                        self.assertions.push(Assertion {
+                            field: field.clone(),
+                            expected: expected_data_substituted.clone(),
+                            actual: Some(actual_data.to_string()),
+                            passed,
+                            soft: false,
+                        });
+                        if !passed {
+                            return Err(Box::new(EngineError::FieldMismatch(format!(
+                                "'{}' field expected '{}', but got '{}'{}",
+                                field,
+                                expected_data_substituted,
+                                actual_data,
+                                line_diff(&expected_data_substituted, actual_data)
+                            ))));
+                        }
+                        if self.plan.output.expect.len() == 1 {
+                            info!("validation successful");
+                            return Ok(());
+                        }
+                    }
+                    None => {
+                        return Err(Box::new(EngineError::MissingField(format!(
+                            "required field '{data_field}' is missing"
+                        ))));
+                    }
                 }
             }
         }
 
         // validating other fields (GET case)
-        let validation_target = match json.get("data") {
-            Some(Value::Object(obj)) => obj,
-            Some(_) => json.as_object().unwrap(),
-            None => json.as_object().unwrap(),
+        let data_base = format!("/{data_field}");
+        let (validation_target, base) = match json.get(&data_field) {
+            Some(data @ (Value::Object(_) | Value::Array(_))) if envelope => {
+                (data, data_base.as_str())
+            }
+            _ => (json, ""),
         };
 
+        // all the fields are checked, so the assertions are complete even on failure
+        let mut failures: Vec<Box<dyn Error>> = Vec::new();
         for (key, expected_value) in &self.plan.output.expect {
-            if key == "code" || key == "data" {
+            if envelope && (*key == code_field || *key == data_field) {
                 continue;
             }
 
-            match validation_target.get(key) {
-                Some(value) => {
-                    let expected_value_substituted = self.substitute_env_vars(expected_value)?;
-                    if !self.compare_values(value, &expected_value_substituted)? {
-                        return Err(Box::new(EngineError::FieldMismatch(format!(
-                            "field '{}' expected '{}' but got '{}'",
-                            key, expected_value_substituted, value
-                        ))));
-                    }
-                }
-                None => {
-                    return Err(Box::new(EngineError::MissingField(format!(
-                        "required field '{}' is missing",
-                        key
-                    ))));
+            // advisory expectation, whose mismatch is just a warning
+            let (soft, expected_value) = match expected_value.strip_prefix("soft:") {
+                Some(expected_value) => (true, expected_value.trim_start()),
+                None => (false, expected_value.as_str()),
+            };
+            let expected_value_substituted = self.substitute_expected(expected_value)?;
+            let field = self.field_name(validation_target, base, key);
+            // `length` of array response, unless it's the name of a field
+            let length;
+            let actual = match (key.as_str(), validation_target) {
+                ("length", Value::Array(items)) => {
+                    length = Value::from(items.len());
+                    Some(&length)
                 }
+                _ => lookup(validation_target, key),
+            };
+            let failure: Option<Box<dyn Error>> = match actual {
+                // presence sentinels are checked instead of the value, i.e. of leaked secrets,
+                // or `next_cursor` of the last page (where `null` counts as absent)
+                Some(_) if expected_value == "@exists" => None,
+                None if expected_value == "@absent" => None,
+                None | Some(Value::Null) if expected_value == "@null_or_absent" => None,
+                Some(value) if expected_value == "@absent" => Some(
+                    EngineError::FieldMismatch(format!(
+                        "field '{field}' expected to be absent, but got '{value}'"
+                    ))
+                    .into(),
+                ),
+                Some(value) if expected_value == "@null_or_absent" => Some(
+                    EngineError::FieldMismatch(format!(
+                        "field '{field}' expected to be null or absent, but got '{value}'"
+                    ))
+                    .into(),
+                ),
+                Some(value) => match self.compare_values(value, &expected_value_substituted) {
+                    Ok(true) => None,
+                    Ok(false) => Some(
+                        EngineError::FieldMismatch(format!(
+                            "field '{}' expected '{}' but got '{}'{}",
+                            field,
+                            expected_value_substituted,
+                            value,
+                            line_diff(&expected_value_substituted, value)
+                        ))
+                        .into(),
+                    ),
+                    Err(e) => Some(with_field(e, &field)),
+                },
+                None => Some(
+                    EngineError::MissingField(format!(
+                        "required field '{}' is missing{}",
+                        field,
+                        missing_hint(validation_target, key)
+                    ))
+                    .into(),
+                ),
+            };
+
+            self.assertions.push(Assertion {
+                field,
+                expected: expected_value_substituted,
+                actual: actual.map(Value::to_string),
+                passed: failure.is_none(),
+                soft,
+            });
+            match failure {
+                Some(failure) if soft => warn!("soft assertion failed: {}", failure),
+                failure => failures.extend(failure),
             }
         }
 
+        if let Some(failure) = failures.into_iter().next() {
+            return Err(failure);
+        }
         info!("validation successful");
         Ok(())
     }
 
+    // field name for error messages, JSON Pointer into the response with --json-pointer-errors
+    fn field_name(&self, target: &Value, base: &str, key: &str) -> String {
+        if self.settings.json_pointer_errors {
+            json_pointer(target, base, key)
+        } else {
+            key.to_string()
+        }
+    }
+
     fn compare_values(&self, value: &Value, expected: &str) -> Result<bool, Box<dyn Error>> {
-        debug!("compare_values: {value} and \"{expected}\"");
-        match value {
-            Value::Number(n) => {
-                let actual = n.as_i64().ok_or("integer expected")?;
-                if expected.starts_with('>') {
-                    println!(
-                        "actual: {actual}, expected: {}",
-                        expected[1..].parse::<i64>()?
-                    );
-                    Ok(actual > expected[1..].parse::<i64>()?)
-                } else if expected.starts_with('<') {
-                    Ok(actual < expected[1..].parse::<i64>()?)
-                } else {
-                    Ok(actual == expected.parse::<i64>()?)
+        debug!(
+            "compare_values: {} and \"{}\"",
+            mask(&value.to_string(), &self.secrets()),
+            mask(expected, &self.secrets())
+        );
+        if let Some(command) = expected.strip_prefix("cmd:") {
+            return self.compare_with_command(value, command.trim());
+        }
+        if expected == "@null_or_absent" {
+            return Ok(value.is_null());
+        }
+        // JSON type regardless of the value: `@type:number`
+        if let Some(kind) = expected.strip_prefix("@type:") {
+            return Ok(match kind.trim() {
+                "string" => value.is_string(),
+                "number" => value.is_number(),
+                "bool" => value.is_boolean(),
+                "array" => value.is_array(),
+                "object" => value.is_object(),
+                "null" => value.is_null(),
+                other => {
+                    return Err(Box::new(EngineError::InvalidPlan(format!(
+                        "unknown type '{other}' (string, number, bool, array, object, null)"
+                    ))));
                 }
-            }
+            });
+        }
+        // ASCII case-insensitive equality, i.e. of status strings varying by server
+        if let Some(expected) = expected.strip_prefix("~i=") {
+            let text = match value {
+                Value::String(s) => s.clone(),
+                other => other.to_string(),
+            };
+            return Ok(text.eq_ignore_ascii_case(expected));
+        }
+        if let Some(pattern) = expected.strip_prefix("~=") {
+            let text = match value {
+                Value::String(s) => s.clone(),
+                other => other.to_string(),
+            };
+            return Ok(cached_regex(pattern)?.is_match(&text));
+        }
+        // explicit (in)equality, i.e. with a value, captured from an earlier response
+        if let Some(expected) = expected.strip_prefix("!=") {
+            return Ok(!values_equal(value, expected));
+        }
+        if let Some(expected) = expected.strip_prefix('=') {
+            return Ok(values_equal(value, expected));
+        }
+        // membership in the set of options: `oneof:active,pending,closed`
+        if let Some(options) = expected.strip_prefix("oneof:") {
+            return Ok(options
+                .split(',')
+                .any(|option| values_equal(value, option.trim())));
+        }
+        // strict numeric types: `5` is an integer, while `5.0` is a float
+        if let Some(expected) = expected.strip_prefix("int:") {
+            return Ok(value.is_i64() && self.compare_values(value, expected.trim())?);
+        }
+        if let Some(expected) = expected.strip_prefix("float:") {
+            return match value.as_f64() {
+                Some(actual) if value.is_f64() => compare_f64(actual, expected.trim()),
+                _ => Ok(false),
+            };
+        }
+        // inline JSON object is compared as a subset: extra fields of the response are allowed
+        if expected.starts_with('{')
+            && let Ok(expected) = serde_json::from_str::<Value>(expected)
+        {
+            return match subset_mismatch(&expected, value, "") {
+                None => Ok(true),
+                Some(mismatch) => Err(Box::new(EngineError::FieldMismatch(mismatch))),
+            };
+        }
+        // exact array, either ordered `[1, 2, 3]`, or regardless of the order `set:[1, 2, 3]`
+        let (list, ordered) = match expected.strip_prefix("set:") {
+            Some(list) => (list.trim(), false),
+            None => (expected, true),
+        };
+        if let Value::Array(items) = value
+            && list.starts_with('[')
+            && let Ok(Value::Array(expected)) = serde_json::from_str::<Value>(list)
+        {
+            return match array_mismatch(&expected, items, ordered) {
+                None => Ok(true),
+                Some(mismatch) => Err(Box::new(EngineError::FieldMismatch(mismatch))),
+            };
+        }
+        // array membership regardless of position: `contains:vip`,
+        // or by a field of object items: `contains:id=5`
+        if let Some(member) = expected.strip_prefix("contains:") {
+            let Value::Array(items) = value else {
+                // substring of a string, i.e. of a human-readable message with dynamic data
+                return Ok(value.as_str().is_some_and(|s| s.contains(member)));
+            };
+            return Ok(items
+                .iter()
+                .any(|item| match (item, member.split_once('=')) {
+                    (Value::Object(_), Some((field, expected))) => {
+                        lookup(item, field).is_some_and(|actual| values_equal(actual, expected))
+                    }
+                    _ => values_equal(item, member),
+                }));
+        }
+        // array length: `len:3`, `>len:0`, `<len:10`
+        if let Some((op, count)) = expected.split_once("len:")
+            && matches!(op, "" | ">" | "<")
+        {
+            return match value {
+                Value::Array(items) => compare_i64(items.len() as i64, &format!("{op}{count}")),
+                _ => Ok(false),
+            };
+        }
+        match value {
+            // integers are compared exactly, while floats (either side) as f64
+            Value::Number(n) => match n.as_i64() {
+                Some(actual) if is_integer_expectation(expected) => compare_i64(actual, expected),
+                _ => compare_f64(n.as_f64().ok_or("number expected")?, expected),
+            },
             Value::String(s) => Ok(s == expected),
             Value::Bool(b) => match expected {
                 "true" => Ok(*b),
                 "false" => Ok(!*b),
                 _ => Ok(false),
             },
+            Value::Null => Ok(expected == "null"),
             _ => Ok(false),
         }
     }
 
-    fn assign_output(&mut self, json: &Value) -> Result<(), Box<dyn Error>> {
+    // runs external command with the actual value piped into its stdin (and given as `$1`),
+    // zero exit code means match
+    fn compare_with_command(&self, value: &Value, command: &str) -> Result<bool, Box<dyn Error>> {
+        let input = match value {
+            Value::String(s) => s.clone(),
+            other => other.to_string(),
+        };
+
+        debug!("executing comparator: {command}");
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .arg("sh")
+            .arg(&input)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        // the command might not read its stdin, i.e. checking `$1` only
+        if let Some(mut stdin) = child.stdin.take()
+            && let Err(e) = stdin.write_all(input.as_bytes())
+            && e.kind() != ErrorKind::BrokenPipe
+        {
+            return Err(Box::new(e));
+        }
+        let output = child.wait_with_output()?;
+
+        if output.status.success() {
+            Ok(true)
+        } else {
+            Err(Box::new(EngineError::FieldMismatch(format!(
+                "command '{command}' rejected '{input}' ({}): {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ))))
+        }
+    }
+
+    fn assign_output(
+        &mut self,
+        response: &Response,
+        json: &Value,
+        elapsed_ms: u128,
+    ) -> Result<(), Box<dyn Error>> {
+        // besides `$VAR`, each var is also available as `$namespace.VAR` to survive reassignments
+        let namespace = self
+            .plan
+            .output
+            .namespace
+            .clone()
+            .unwrap_or_else(|| self.plan.name.clone());
         if let Some(assign_map) = &self.plan.output.assign {
             for (key, var_name) in assign_map {
-                if let Some(value) = json.get(key) {
-                    let string_value = value.to_string().replace("\"", "");
-                    unsafe {
-                        env::set_var(var_name.trim_start_matches('$'), &string_value);
+                // synthetic keys are measured, rather than looked up in the response,
+                // `header:Location` is looked up among response headers, case-insensitively
+                let value = match key.as_str() {
+                    "@response_ms" => Some(Value::from(elapsed_ms as u64)),
+                    _ => match key.strip_prefix("header:") {
+                        Some(name) => response.header(name).map(Value::String),
+                        None => lookup(json, key).cloned(),
+                    },
+                };
+                if let Some(value) = value {
+                    // strings are kept as is, so they are compared exactly later on,
+                    // while the rest (including strings inside of arrays and objects) as JSON
+                    let string_value = match value {
+                        Value::String(s) => s,
+                        other => other.to_string(),
+                    };
+                    // vars are kept without `$`, however the assign target is written
+                    let bare_name = var_name.trim_start_matches('$');
+                    let mut vars = self
+                        .env_vars
+                        .write()
+                        .unwrap_or_else(PoisonError::into_inner);
+                    if !namespace.is_empty() {
+                        vars.insert(format!("{namespace}.{bare_name}"), string_value.clone());
                     }
-                    self.env_vars.insert(var_name.clone(), string_value.clone());
-                    info!("assigned {string_value} to {var_name} var");
+                    vars.insert(bare_name.to_string(), string_value.clone());
+                    info!("assigned {string_value} to ${bare_name} var");
                 }
             }
         }
         Ok(())
     }
 
+    // only `name=value` of each cookie is kept, attributes (`Path`, `Expires`, ...) are ignored,
+    // and the cookie of empty value is removed from the jar
+    fn store_cookies(&mut self, response: &Response) {
+        for (name, value) in &response.headers {
+            if name != "set-cookie" {
+                continue;
+            }
+            let pair = value.split(';').next().unwrap_or_default();
+            let Some((cookie, value)) = pair.split_once('=') else {
+                continue;
+            };
+            let (cookie, value) = (cookie.trim(), value.trim());
+            if value.is_empty() {
+                self.cookies.shift_remove(cookie);
+            } else {
+                debug!("stored {cookie} cookie");
+                self.cookies.insert(cookie.to_string(), value.to_string());
+            }
+        }
+    }
+
+    fn cookie_header(&self) -> Option<String> {
+        if !self.plan.plan.cookies || self.cookies.is_empty() {
+            return None;
+        }
+        let pairs: Vec<String> = self
+            .cookies
+            .iter()
+            .map(|(name, value)| format!("{name}={value}"))
+            .collect();
+        Some(pairs.join("; "))
+    }
+
+    // raw body of the passed response, i.e. generated artifact or snapshot for later inspection
+    fn save_response(&self, response: &Response) -> Result<(), Box<dyn Error>> {
+        let Some(path) = &self.plan.output.save_to else {
+            return Ok(());
+        };
+        let path = self
+            .plan
+            .dir
+            .join(self.substitute_env_vars(&path.to_string_lossy())?);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&path, &response.body)?;
+        info!("response is saved to {}", path.display());
+        Ok(())
+    }
+
+    // assigned vars go first, then process env and `--env-file` (in the configured order)
+    // only the first `visible_vars` entries of `[vars]` are looked up, so an entry might refer
+    // to the preceding ones, but never to itself
+    fn lookup_var(&self, var_name: &str, visible_vars: usize) -> Option<String> {
+        let bare_name = var_name.trim_start_matches('$');
+        // vars of the current data row (or matrix entry) win, as they parameterize the testplan
+        if let Some(value) = self.plan.row_vars.get(bare_name) {
+            return Some(value.clone());
+        }
+        let plan_var = || {
+            let (index, _, value) = self.plan.vars.get_full(bare_name)?;
+            if index >= visible_vars {
+                return None;
+            }
+            self.substitute(&var_value(value), index, str::to_string)
+                .ok()
+        };
+        if self.plan.plan.vars_override
+            && let Some(value) = plan_var()
+        {
+            return Some(value);
+        }
+        let assigned = self
+            .env_vars
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(bare_name)
+            .cloned();
+        let from_file = || self.settings.env_file.get(bare_name).cloned();
+        let from_env = || env::var(bare_name).ok();
+        let value = if self.settings.env_file_override {
+            assigned
+                .or_else(from_file)
+                .or_else(from_env)
+                .or_else(plan_var)
+        } else {
+            assigned
+                .or_else(from_env)
+                .or_else(from_file)
+                .or_else(plan_var)
+        };
+        value.or_else(|| {
+            let mut rng = self.rng.lock().unwrap_or_else(PoisonError::into_inner);
+            generated_value(bare_name, &mut *rng)
+        })
+    }
+
     fn substitute_env_vars(&self, text: &str) -> Result<String, Box<dyn Error>> {
-        let re = Regex::new(r"\$[A-Za-z0-9_]+").unwrap();
-        let mut result = text.to_string();
-
-        for cap in re.captures_iter(text) {
-            let var_name = cap.get(0).unwrap().as_str();
-            let env_var_name = var_name.trim_start_matches('$');
-
-            let env_value = env::var(env_var_name).or_else(|_| {
-                self.env_vars
-                    .get(var_name)
-                    .map(|s| s.clone())
-                    .ok_or(std::env::VarError::NotPresent)
-            });
+        self.substitute(text, self.plan.vars.len(), str::to_string)
+    }
+
+    // `cmd:` comparator is run verbatim, so values of responses never become part of the shell
+    // command, it gets the actual value via stdin and `$1` instead
+    fn substitute_expected(&self, expected: &str) -> Result<String, Box<dyn Error>> {
+        if expected.starts_with("cmd:") {
+            return Ok(expected.to_string());
+        }
+        self.substitute_env_vars(expected)
+    }
+
+    // substituted values are percent-encoded, so spaces or `&` of them don't break the URL
+    fn substitute_url(&self, text: &str) -> Result<String, Box<dyn Error>> {
+        self.substitute(text, self.plan.vars.len(), encode_url_value)
+    }
+
+    fn substitute(
+        &self,
+        text: &str,
+        visible_vars: usize,
+        encode: fn(&str) -> String,
+    ) -> Result<String, Box<dyn Error>> {
+        // `${VAR}` delimits the name explicitly, so `${ID}foo` differs from `$IDfoo`;
+        // `${VAR:-default}` falls back to the default, `${VAR:?message}` fails with the message
+        static VAR_RE: LazyLock<Regex> = LazyLock::new(|| {
+            Regex::new(concat!(
+                r"\$\{([A-Za-z0-9_]+(?:\.[A-Za-z0-9_]+)?)(?::([-?])([^}]*))?\}",
+                r"|\$([A-Za-z0-9_]+)(\.[A-Za-z0-9_]+)?",
+            ))
+            .unwrap()
+        });
+        let mut result = String::with_capacity(text.len());
+        let mut last = 0;
+
+        for cap in VAR_RE.captures_iter(text) {
+            let whole = cap.get(0).unwrap();
+            result.push_str(&text[last..whole.start()]);
+            last = whole.end();
+
+            let var_name = match (cap.get(1), cap.get(4)) {
+                (Some(braced), _) => format!("${}", braced.as_str()),
+                (None, Some(name)) => {
+                    // unknown `$namespace.VAR` is rather `$VAR` followed by a text,
+                    // i.e. `$FILE.json`
+                    if cap.get(5).is_some()
+                        && self.lookup_var(whole.as_str(), visible_vars).is_none()
+                    {
+                        last = name.end();
+                        format!("${}", name.as_str())
+                    } else {
+                        whole.as_str().to_string()
+                    }
+                }
+                (None, None) => continue,
+            };
+            let operand = cap.get(3).map_or("", |operand| operand.as_str());
 
-            match env_value {
-                Ok(value) => {
-                    result = result.replace(var_name, &value);
+            match (
+                self.lookup_var(&var_name, visible_vars),
+                cap.get(2).map(|op| op.as_str()),
+            ) {
+                (Some(value), _) => result.push_str(&encode(&value)),
+                (None, Some("-")) => result.push_str(&encode(operand)),
+                (None, Some(_)) if !operand.is_empty() => {
+                    return Err(Box::new(EngineError::MissingField(operand.to_string())));
                 }
-                Err(_) => {
+                _ => {
                     return Err(Box::new(EngineError::MissingField(format!(
                         "env var {} not found",
                         var_name
@@ -260,7 +1826,530 @@ impl TestEngine {
                 }
             }
         }
+        result.push_str(&text[last..]);
 
         Ok(result)
     }
 }
+
+// effective testplan, with vars substituted (unresolved ones are kept as is) and secrets masked
+impl fmt::Display for TestEngine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // the one resolved to be sent (of `--explain`), if any
+        let request = match &self.last_request {
+            Some(request) => Cow::Borrowed(request),
+            None => Cow::Owned(self.unresolved_request()),
+        };
+        let secrets = &request.secrets;
+        let resolve = |text: &str| {
+            let text = self
+                .substitute_env_vars(text)
+                .unwrap_or_else(|_| text.to_string());
+            mask(&text, secrets)
+        };
+        let output = &self.plan.output;
+
+        writeln!(f, "testplan: {}", self.plan.name)?;
+        writeln!(f, "  executor: {}", self.plan.plan.executor)?;
+        if let Some(curl_args) = &self.plan.plan.curl_args {
+            writeln!(f, "  curl args: {}", curl_args.join(" "))?;
+        }
+        writeln!(
+            f,
+            "  request: {} {}",
+            request.method,
+            mask(&request.url, secrets)
+        )?;
+        match &request.credentials {
+            Some(Credentials::Header(name, _)) => writeln!(f, "  header: {name}: ***")?,
+            Some(Credentials::Basic(username, _)) => writeln!(f, "  auth: basic {username}:***")?,
+            None => {}
+        }
+        for (name, value) in &request.headers {
+            writeln!(
+                f,
+                "  header: {name}: {}",
+                mask(mask_header(name, value), secrets)
+            )?;
+        }
+        match &request.body {
+            Some(Body::Raw(body)) if !body.is_empty() => {
+                writeln!(f, "  body: {}", mask(body, secrets))?
+            }
+            Some(Body::Multipart(_, files)) => {
+                for (name, path) in files {
+                    writeln!(f, "  file: {name} = {}", path.display())?;
+                }
+            }
+            _ => {}
+        }
+        for (key, expected) in &output.expect {
+            writeln!(f, "  expect: {key} = {}", resolve(expected))?;
+        }
+        for (name, expected) in &output.headers {
+            writeln!(
+                f,
+                "  expect header: {} = {}",
+                resolve(name),
+                resolve(expected)
+            )?;
+        }
+        if let Some(assign) = &output.assign {
+            for (key, var_name) in assign {
+                writeln!(f, "  assign: {key} -> {var_name}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// request with vars substituted once per send, so the logged, explained and reported values
+// are the ones sent
+#[derive(Debug, Clone)]
+struct Request {
+    method: String,
+    url: String,
+    credentials: Option<Credentials>,
+    // headers of the testplan
+    headers: Vec<(String, String)>,
+    body: Option<Body>,
+    // values, which are never logged as is
+    secrets: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+enum Body {
+    // JSON, form or text one
+    Raw(String),
+    // fields and files of multipart form
+    Multipart(Vec<(String, String)>, Vec<(String, PathBuf)>),
+}
+
+// credentials of the request
+#[derive(Debug, Clone)]
+enum Credentials {
+    // header name and value, i.e. `Authorization` and the token
+    Header(String, String),
+    // HTTP Basic username and password
+    Basic(String, String),
+}
+
+// options of the native HTTP client
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct ClientOptions {
+    // none for not following redirects
+    max_redirects: Option<usize>,
+    // accept invalid TLS certificates
+    insecure: bool,
+    // none for the proxy of env vars, if any
+    proxy: Option<String>,
+    // static resolver overrides of hosts
+    resolve: Vec<(String, SocketAddr)>,
+}
+
+// client per distinct options, shared by all the testplans of the run, so connections
+// (and TLS sessions) are reused from its pool instead of a new handshake per request
+fn shared_client(options: ClientOptions) -> Result<Client, Box<dyn Error>> {
+    static CLIENTS: LazyLock<Mutex<HashMap<ClientOptions, Client>>> =
+        LazyLock::new(Default::default);
+    let mut clients = CLIENTS.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Some(client) = clients.get(&options) {
+        return Ok(client.clone());
+    }
+    let redirect = match options.max_redirects {
+        Some(max_redirects) => Policy::limited(max_redirects),
+        None => Policy::none(),
+    };
+    let mut builder = Client::builder()
+        .redirect(redirect)
+        .danger_accept_invalid_certs(options.insecure);
+    if let Some(proxy) = &options.proxy {
+        builder = builder.proxy(Proxy::all(proxy)?);
+    }
+    for (host, address) in &options.resolve {
+        builder = builder.resolve(host, *address);
+    }
+    let client = builder.build()?;
+    clients.insert(options, client.clone());
+    Ok(client)
+}
+
+// first chars of the body are kept for context, i.e. when HTML error page is returned
+fn parse_json(body: &str) -> Result<Value, EngineError> {
+    const SNIPPET_LEN: usize = 200;
+    serde_json::from_str(body).map_err(|source| EngineError::JsonParse {
+        snippet: body.chars().take(SNIPPET_LEN).collect(),
+        source,
+    })
+}
+
+// responses of known length below it are parsed as a whole, even with `stream = true`
+const STREAM_MIN_BYTES: u64 = 64 * 1024;
+
+// fresh value of the reserved var (unless it's defined), generated per substitution
+fn generated_value(name: &str, rng: &mut impl Rng) -> Option<String> {
+    match name {
+        "UUID" => {
+            let mut bytes = [0u8; 16];
+            rng.fill(&mut bytes);
+            // version 4, variant RFC 4122
+            bytes[6] = (bytes[6] & 0x0f) | 0x40;
+            bytes[8] = (bytes[8] & 0x3f) | 0x80;
+            let hex: String = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+            Some(format!(
+                "{}-{}-{}-{}-{}",
+                &hex[..8],
+                &hex[8..12],
+                &hex[12..16],
+                &hex[16..20],
+                &hex[20..]
+            ))
+        }
+        "TIMESTAMP" => Some(Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true)),
+        "RANDOM_INT" => Some(rng.gen_range(0..1_000_000_000).to_string()),
+        _ => None,
+    }
+}
+
+// numeric header (i.e. of rate limits) is compared as a number, the rest as strings
+fn header_value(text: &str) -> Value {
+    serde_json::from_str::<Value>(text)
+        .ok()
+        .filter(Value::is_number)
+        .unwrap_or_else(|| Value::String(text.to_string()))
+}
+
+fn encode_query_component(text: &str) -> String {
+    percent_encode(text, b"")
+}
+
+// var value within URL, where path fragments (`/v1/customer`) and absolute URLs are kept intact
+fn encode_url_value(text: &str) -> String {
+    percent_encode(text, b"/:@")
+}
+
+// percent-encodes everything except RFC 3986 unreserved chars, and the `keep` ones
+fn percent_encode(text: &str, keep: &[u8]) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ if keep.contains(&byte) => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+// reads JSON from env var, i.e. computed by an earlier CI step
+fn env_json(var: &str) -> Result<Value, EngineError> {
+    let json = env::var(var)
+        .map_err(|_| EngineError::MissingField(format!("env var {var} with JSON is not set")))?;
+    serde_json::from_str(&json)
+        .map_err(|e| EngineError::InvalidPlan(format!("env var {var} is not a valid JSON: {e}")))
+}
+
+// status pattern is either exact (`404`), or with `x` wildcards (`2xx`)
+fn status_matches(pattern: &str, status: u16) -> bool {
+    let status = status.to_string();
+    pattern.len() == status.len()
+        && pattern
+            .chars()
+            .zip(status.chars())
+            .all(|(p, s)| p.eq_ignore_ascii_case(&'x') || p == s)
+}
+
+// first mismatch of `actual` against `expected` subset, as JSON Pointer relative to the field;
+// objects may have extra fields, while arrays are compared item by item
+fn subset_mismatch(expected: &Value, actual: &Value, path: &str) -> Option<String> {
+    let at = if path.is_empty() { "/" } else { path };
+    match (expected, actual) {
+        (Value::Object(expected), Value::Object(actual)) => {
+            expected.iter().find_map(|(key, expected)| {
+                let path = format!("{path}/{key}");
+                match actual.get(key) {
+                    Some(actual) => subset_mismatch(expected, actual, &path),
+                    None => Some(format!("'{path}' is missing")),
+                }
+            })
+        }
+        (Value::Array(expected_items), Value::Array(actual_items)) => {
+            if expected_items.len() != actual_items.len() {
+                return Some(format!(
+                    "'{at}' expected {} items, but got {}",
+                    expected_items.len(),
+                    actual_items.len()
+                ));
+            }
+            expected_items
+                .iter()
+                .zip(actual_items)
+                .enumerate()
+                .find_map(|(idx, (expected, actual))| {
+                    subset_mismatch(expected, actual, &format!("{path}/{idx}"))
+                })
+        }
+        (Value::Number(expected_number), Value::Number(actual_number))
+            if expected_number.as_f64() == actual_number.as_f64() =>
+        {
+            None
+        }
+        _ if expected == actual => None,
+        _ => Some(format!("'{at}' expected {expected}, but got {actual}")),
+    }
+}
+
+// items of the array, which are missing or extra (as multisets), or the order of the same items
+fn array_mismatch(expected: &[Value], actual: &[Value], ordered: bool) -> Option<String> {
+    let mut extra: Vec<&Value> = actual.iter().collect();
+    let mut missing = Vec::new();
+    for item in expected {
+        match extra.iter().position(|actual| items_equal(item, actual)) {
+            Some(index) => {
+                extra.remove(index);
+            }
+            None => missing.push(item),
+        }
+    }
+    let list = |items: Vec<&Value>| Value::Array(items.into_iter().cloned().collect()).to_string();
+    if missing.is_empty() && extra.is_empty() {
+        if !ordered || expected.iter().zip(actual).all(|(e, a)| items_equal(e, a)) {
+            return None;
+        }
+        return Some(format!(
+            "expected items in order {}, but got {}",
+            list(expected.iter().collect()),
+            list(actual.iter().collect())
+        ));
+    }
+    let mut mismatch = Vec::new();
+    if !missing.is_empty() {
+        mismatch.push(format!("missing {}", list(missing)));
+    }
+    if !extra.is_empty() {
+        mismatch.push(format!("extra {}", list(extra)));
+    }
+    Some(format!("array items differ: {}", mismatch.join(", ")))
+}
+
+// numbers are equal by value (`5` and `5.0`), the rest structurally
+fn items_equal(expected: &Value, actual: &Value) -> bool {
+    match (expected.as_f64(), actual.as_f64()) {
+        (Some(expected), Some(actual)) => expected == actual,
+        _ => expected == actual,
+    }
+}
+
+// numbers are equal by value (`5` and `5.0`), arrays and objects as JSON, the rest by their text
+fn values_equal(value: &Value, expected: &str) -> bool {
+    match value {
+        Value::String(s) => s == expected,
+        Value::Number(n) => match (n.as_f64(), expected.parse::<f64>()) {
+            (Some(actual), Ok(expected)) => actual == expected,
+            _ => n.to_string() == expected,
+        },
+        Value::Array(_) | Value::Object(_) => {
+            serde_json::from_str::<Value>(expected).is_ok_and(|expected| expected == *value)
+        }
+        Value::Bool(b) => b.to_string() == expected,
+        Value::Null => expected == "null",
+    }
+}
+
+// regexes of expectations are compiled once per run
+fn cached_regex(pattern: &str) -> Result<Regex, EngineError> {
+    static CACHE: LazyLock<Mutex<HashMap<String, Regex>>> =
+        LazyLock::new(|| Mutex::new(HashMap::new()));
+    let mut cache = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(re) = cache.get(pattern) {
+        return Ok(re.clone());
+    }
+    let re = Regex::new(pattern)
+        .map_err(|e| EngineError::InvalidPlan(format!("invalid regex '{pattern}': {e}")))?;
+    cache.insert(pattern.to_string(), re.clone());
+    Ok(re)
+}
+
+fn is_integer_expectation(expected: &str) -> bool {
+    expected
+        .trim_start_matches(['>', '<'])
+        .parse::<i64>()
+        .is_ok()
+}
+
+fn compare_i64(actual: i64, expected: &str) -> Result<bool, Box<dyn Error>> {
+    if let Some(expected) = expected.strip_prefix('>') {
+        Ok(actual > expected.parse::<i64>()?)
+    } else if let Some(expected) = expected.strip_prefix('<') {
+        Ok(actual < expected.parse::<i64>()?)
+    } else {
+        Ok(actual == expected.parse::<i64>()?)
+    }
+}
+
+// `~` means approximate equality, as exact one is fragile for floats;
+// `10..20` (or `10..=20`) is inclusive range, while `10..<20` excludes its upper bound
+fn compare_f64(actual: f64, expected: &str) -> Result<bool, Box<dyn Error>> {
+    const EPSILON: f64 = 1e-6;
+    if let Some(expected) = expected.strip_prefix('>') {
+        Ok(actual > expected.parse::<f64>()?)
+    } else if let Some(expected) = expected.strip_prefix('<') {
+        Ok(actual < expected.parse::<f64>()?)
+    } else if let Some(expected) = expected.strip_prefix('~') {
+        Ok((actual - expected.parse::<f64>()?).abs() <= EPSILON)
+    } else if let Some((low, high)) = expected.split_once("..") {
+        let above_low = actual >= low.trim().parse::<f64>()?;
+        match high.strip_prefix('<') {
+            Some(high) => Ok(above_low && actual < high.trim().parse::<f64>()?),
+            None => Ok(above_low && actual <= high.trim_start_matches('=').trim().parse::<f64>()?),
+        }
+    } else {
+        Ok(actual == expected.parse::<f64>()?)
+    }
+}
+
+// collects differences between two json values, one line per differing path
+fn diff_values(expected: &Value, actual: &Value, path: &str, diff: &mut Vec<String>) {
+    match (expected, actual) {
+        (Value::Object(expected), Value::Object(actual)) => {
+            for (key, expected_value) in expected {
+                let child = format!("{path}/{key}");
+                match actual.get(key) {
+                    Some(actual_value) => diff_values(expected_value, actual_value, &child, diff),
+                    None => diff.push(format!("  {child}: {expected_value} != <absent>")),
+                }
+            }
+            for (key, actual_value) in actual {
+                if !expected.contains_key(key) {
+                    diff.push(format!("  {path}/{key}: <absent> != {actual_value}"));
+                }
+            }
+        }
+        (Value::Array(expected), Value::Array(actual)) if expected.len() == actual.len() => {
+            for (idx, (expected_item, actual_item)) in expected.iter().zip(actual).enumerate() {
+                diff_values(expected_item, actual_item, &format!("{path}/{idx}"), diff);
+            }
+        }
+        _ if expected != actual => {
+            let path = if path.is_empty() { "/" } else { path };
+            diff.push(format!("  {path}: {expected} != {actual}"));
+        }
+        _ => {}
+    }
+}
+
+// names the field in mismatches, reported by the matchers themselves (subsets, arrays, `cmd:`)
+fn with_field(e: Box<dyn Error>, field: &str) -> Box<dyn Error> {
+    match e.downcast::<EngineError>() {
+        Ok(e) => match *e {
+            EngineError::FieldMismatch(mismatch) => Box::new(EngineError::FieldMismatch(format!(
+                "field '{field}': {mismatch}"
+            ))),
+            e => Box::new(e),
+        },
+        Err(e) => e,
+    }
+}
+
+// line-oriented diff of pretty-printed nested values (`-` expected, `+` actual),
+// empty for scalars, whose mismatch is readable as is
+fn line_diff(expected: &str, actual: &Value) -> String {
+    let pretty = |value: &Value| serde_json::to_string_pretty(value).unwrap_or_default();
+    let expected = expected.strip_prefix('=').unwrap_or(expected);
+    let expected = serde_json::from_str(expected).map_or(expected.to_string(), |v| pretty(&v));
+    let actual = pretty(actual);
+    if !expected.contains('\n') && !actual.contains('\n') {
+        return String::new();
+    }
+    let mut diff = String::from("\n");
+    for change in TextDiff::from_lines(&expected, &actual).iter_all_changes() {
+        let sign = match change.tag() {
+            ChangeTag::Delete => '-',
+            ChangeTag::Insert => '+',
+            ChangeTag::Equal => ' ',
+        };
+        diff.push_str(&format!("  {sign} {}", change.value()));
+        if change.missing_newline() {
+            diff.push('\n');
+        }
+    }
+    diff.truncate(diff.trim_end().len());
+    diff
+}
+
+// converts dot-separated path within json into RFC 6901 JSON Pointer, prefixed with base
+fn json_pointer(json: &Value, base: &str, path: &str) -> String {
+    let escape = |segment: &str| segment.replace('~', "~0").replace('/', "~1");
+    if json.get(path).is_some() {
+        return format!("{base}/{}", escape(path));
+    }
+    path.split('.').fold(base.to_string(), |pointer, segment| {
+        format!("{pointer}/{}", escape(segment))
+    })
+}
+
+// converts basic JSONPath into dot-separated path, leaving others as is
+fn from_json_path(path: &str) -> Cow<'_, str> {
+    match path.strip_prefix('$') {
+        Some(rest) if rest.is_empty() || rest.starts_with(['.', '[']) => {
+            let dotted = rest
+                .replace("['", ".")
+                .replace("']", "")
+                .replace('[', ".")
+                .replace(']', "");
+            Cow::Owned(dotted.trim_start_matches('.').to_string())
+        }
+        _ => Cow::Borrowed(path),
+    }
+}
+
+// shortest prefix of nested path, which is missing in json (none for a plain key)
+fn missing_segment<'a>(json: &Value, path: &'a str) -> Option<&'a str> {
+    if !path.contains('.') {
+        return None;
+    }
+    let mut value = json;
+    let mut end = 0;
+    for segment in path.split('.') {
+        end += segment.len();
+        match lookup(value, segment) {
+            Some(child) => value = child,
+            None => return Some(&path[..end]),
+        }
+        // skipping the dot
+        end += 1;
+    }
+    None
+}
+
+// explains the missing field: either its missing prefix, or object-style key on array
+fn missing_hint(json: &Value, path: &str) -> String {
+    let first = from_json_path(path)
+        .split('.')
+        .next()
+        .unwrap_or_default()
+        .to_string();
+    if json.is_array() && first.parse::<usize>().is_err() {
+        return format!(
+            " (response is an array, so '{first}' should be either an index, or 'length')"
+        );
+    }
+    missing_segment(json, path)
+        .map(|segment| format!(" (no '{segment}')"))
+        .unwrap_or_default()
+}
+
+// resolves dot-separated path (i.e. `meta.next_cursor`, `items.0.id`) within json,
+// or the basic JSONPath (`$.items[0].id`, `$['meta']['next_cursor']`)
+fn lookup<'a>(json: &'a Value, path: &str) -> Option<&'a Value> {
+    if let Some(value) = json.get(path) {
+        return Some(value);
+    }
+    let path = from_json_path(path);
+    path.split('.').try_fold(json, |value, key| match value {
+        Value::Array(items) => key.parse::<usize>().ok().and_then(|idx| items.get(idx)),
+        _ => value.get(key),
+    })
+}
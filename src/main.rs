@@ -1,12 +1,54 @@
 use argh::FromArgs;
-use log::{error, info};
-use std::{fs, io, path::PathBuf, process};
+use log::{debug, error, info, warn};
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use std::{
+    cmp::Reverse,
+    collections::{BTreeSet, HashSet},
+    env,
+    error::Error,
+    fs,
+    io::{self, IsTerminal},
+    path::{Path, PathBuf},
+    process,
+    str::FromStr,
+    sync::{
+        Mutex, PoisonError,
+        atomic::{AtomicBool, Ordering},
+        mpsc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
 
+mod bench;
+mod cache;
+mod config;
 mod engine;
 mod plan;
+mod replay;
+mod report;
+mod response;
+mod stream;
 
-use engine::TestEngine;
+use cache::Cache;
+use config::{Layer, Settings};
+use engine::{TestEngine, Vars};
 use plan::TestPlan;
+use report::PlanResult;
+
+// testplan file, along with the loaded testplan or the error of its loading
+type Loaded = (PathBuf, Result<TestPlan, String>);
+
+// skip reason of the testplans after the first failure with --fail-fast
+const ABORTED: &str = "aborted by previous failure";
+
+// quiet period after the last change, before testplans are re-run in watch mode
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(300);
+
+// nothing to run (i.e. of a mistyped path or tag), told apart from failures by the exit code
+#[derive(Debug, thiserror::Error)]
+#[error("no testplan(s) found")]
+struct NoTestplans;
 
 #[derive(FromArgs, PartialEq, Debug)]
 /// tstit - Test It. REST It.
@@ -15,6 +57,95 @@ struct Args {
     /// path(s) to testplan TOML files or directories containing testplans
     paths: Vec<PathBuf>,
 
+    #[argh(option)]
+    /// run only testplans having the given tag (repeatable)
+    tag: Vec<String>,
+
+    #[argh(option)]
+    /// run only the testplan of the given name (or file stem)
+    name: Option<String>,
+
+    #[argh(option)]
+    /// path to a recorded request log (JSON lines of method, path, body) to replay
+    replay: Option<PathBuf>,
+    #[argh(option)]
+    /// expectation applied to every replayed request, as key=value (repeatable)
+    replay_expect: Vec<String>,
+
+    #[argh(option)]
+    /// base URL of the service under test (overrides TSTIT_URL)
+    url: Option<String>,
+    #[argh(option)]
+    /// path prefix between base URL and testplan url (overrides TSTIT_BASE_PATH)
+    base_path: Option<String>,
+    #[argh(option)]
+    /// value of Authorization header (overrides TSTIT_TKN)
+    token: Option<String>,
+    #[argh(option)]
+    /// request timeout, in seconds (overrides TSTIT_TIMEOUT)
+    timeout: Option<u64>,
+    #[argh(option)]
+    /// path to config file (default: tstit.toml in current or any parent directory)
+    config: Option<PathBuf>,
+    #[argh(option)]
+    /// name of config file profile to use
+    profile: Option<String>,
+    #[argh(option)]
+    /// path to .env file with KEY=VALUE vars for substitution
+    env_file: Option<PathBuf>,
+    #[argh(switch)]
+    /// let vars of --env-file override process env ones
+    env_file_override: bool,
+
+    #[argh(switch)]
+    /// report failing fields as JSON Pointers into the response
+    json_pointer_errors: bool,
+
+    #[argh(switch)]
+    /// skip validation of responses identical to the last passed ones (cached in .tstit/)
+    skip_unchanged: bool,
+
+    #[argh(option)]
+    /// write Markdown report of the run into the given file
+    report_md: Option<PathBuf>,
+    #[argh(option)]
+    /// write JUnit XML report of the run into the given file
+    report_junit: Option<PathBuf>,
+    #[argh(option)]
+    /// write self-contained HTML report of the run into the given file
+    report_html: Option<PathBuf>,
+
+    #[argh(switch)]
+    /// print each effective testplan (vars substituted, secrets masked) before its execution
+    explain: bool,
+    #[argh(switch)]
+    /// print effective testplans without executing them
+    dry_run: bool,
+
+    #[argh(switch)]
+    /// stop the run on the first failed testplan, the rest are reported as skipped
+    fail_fast: bool,
+
+    #[argh(option, default = "1")]
+    /// number of testplans of the same priority, run concurrently (default: 1)
+    jobs: usize,
+
+    #[argh(switch)]
+    /// disable colored diffs of failures (also disabled when stderr is not a terminal)
+    no_color: bool,
+
+    #[argh(option, default = "Format::Log")]
+    /// output format: log (default), tap, json
+    format: Format,
+
+    #[argh(switch)]
+    /// after the run, re-run changed testplans on save until Ctrl-C
+    watch: bool,
+
+    #[argh(option)]
+    /// seed of generated vars ($UUID, $RANDOM_INT), for reproducible runs
+    seed: Option<u64>,
+
     #[argh(switch, short = 'v')]
     /// enable verbose output
     verbose: bool,
@@ -23,12 +154,47 @@ struct Args {
     version: bool,
 }
 
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum Format {
+    // human readable log
+    Log,
+    // Test Anything Protocol on stdout, log is suppressed
+    Tap,
+    // single JSON document of the run on stdout, log is suppressed
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "log" => Ok(Format::Log),
+            "tap" => Ok(Format::Tap),
+            "json" => Ok(Format::Json),
+            _ => Err(format!(
+                "unsupported {format} format, expected: log, tap, json"
+            )),
+        }
+    }
+}
+
 fn main() -> Result<(), io::Error> {
+    // `bench` subcommand has its own args, so it goes before the regular ones are parsed
+    let raw_args: Vec<String> = env::args().collect();
+    if raw_args.get(1).map(String::as_str) == Some("bench") {
+        process::exit(bench::main(&raw_args[2..]));
+    }
+
     let args: Args = argh::from_env();
 
+    // machine readable output goes to stdout instead of the log
+    let machine = args.format != Format::Log;
     pretty_env_logger::formatted_builder()
         .filter_level(if args.verbose {
             log::LevelFilter::Debug
+        } else if machine {
+            log::LevelFilter::Off
         } else {
             log::LevelFilter::Info
         })
@@ -39,58 +205,599 @@ fn main() -> Result<(), io::Error> {
         process::exit(0);
     }
 
-    if args.paths.is_empty() {
+    if args.paths.is_empty() && args.replay.is_none() {
         error!("no testplan paths provided");
         println!("try:  tstit --help");
         process::exit(1);
     }
 
-    println!(
-        "{} v{} - {}",
-        env!("CARGO_PKG_NAME"),
-        env!("CARGO_PKG_VERSION"),
-        env!("CARGO_PKG_DESCRIPTION")
-    );
+    if !machine {
+        println!(
+            "{} v{} - {}",
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION"),
+            env!("CARGO_PKG_DESCRIPTION")
+        );
+    }
+
+    let settings = match resolve_settings(&args) {
+        Ok(settings) => settings,
+        Err(e) => {
+            error!("failed to resolve settings: {}", e);
+            process::exit(2);
+        }
+    };
+
+    let fail_count = match run(&args, &args.paths, &settings, true) {
+        Ok(fail_count) => fail_count,
+        Err(e) => {
+            error!("{}", e);
+            process::exit(if e.is::<NoTestplans>() { 3 } else { 2 });
+        }
+    };
+    if args.watch
+        && let Err(e) = watch(&args, &settings)
+    {
+        error!("failed to watch for changes: {}", e);
+        process::exit(2);
+    }
+    if fail_count > 0 {
+        process::exit(1);
+    }
+    Ok(())
+}
 
+// collects and executes testplans of the given paths (along with replayed requests),
+// returns the number of failed ones
+fn run(
+    args: &Args,
+    paths: &[PathBuf],
+    settings: &Settings,
+    with_replay: bool,
+) -> Result<usize, Box<dyn Error>> {
     let mut testplans = Vec::new();
-    for path in args.paths {
-        collect_testplans(path, &mut testplans)?;
+    for path in paths {
+        collect_testplans(path.clone(), &mut testplans)?;
     }
     info!("found {} testplans", testplans.len());
-    if testplans.len() == 0 {
-        error!("no testplan(s) found");
-        process::exit(2);
+
+    let mut testplans: Vec<Loaded> = testplans
+        .into_iter()
+        .map(|path| {
+            let plan = TestPlan::load(&path.to_string_lossy()).map_err(|e| e.to_string());
+            (path, plan)
+        })
+        .collect();
+    if !args.tag.is_empty() {
+        // testplans failed to load are kept, so their errors are reported
+        testplans.retain(|(_, plan)| {
+            plan.as_ref().map_or(true, |plan| {
+                plan.plan
+                    .tags
+                    .iter()
+                    .flatten()
+                    .any(|tag| args.tag.contains(tag))
+            })
+        });
+        info!("selected {} testplans by tags", testplans.len());
+    }
+    if let Some(name) = &args.name {
+        testplans.retain(|(_, plan)| {
+            plan.as_ref()
+                .is_ok_and(|plan| plan.plan_name() == name.as_str())
+        });
+        if testplans.is_empty() {
+            warn!("no testplan named {name} found");
+            return Err(Box::new(NoTestplans));
+        }
+        if testplans.len() > 1 {
+            let paths: Vec<String> = testplans
+                .iter()
+                .map(|(path, _)| path.display().to_string())
+                .collect();
+            return Err(format!(
+                "{} testplans named {} found: {}",
+                paths.len(),
+                name,
+                paths.join(", ")
+            )
+            .into());
+        }
+    }
+    // data-driven testplan is executed (and reported) once per data row or matrix entry
+    let mut testplans: Vec<Loaded> = testplans
+        .into_iter()
+        .flat_map(|(path, plan)| match plan.map(TestPlan::variants) {
+            Ok(Ok(rows)) => rows
+                .into_iter()
+                .map(|row| (path.clone(), Ok(row)))
+                .collect(),
+            Ok(Err(e)) => vec![(path, Err(e.to_string()))],
+            Err(e) => vec![(path, Err(e))],
+        })
+        .collect();
+    // higher priority goes first, stable sort keeps lexical order within equal priorities
+    testplans.sort_by_key(|(_, plan)| Reverse(priority(plan)));
+    let testplans = order_by_dependencies(testplans)?;
+
+    let replayed = match &args.replay {
+        Some(log_path) if with_replay => match replay::load(log_path, &args.replay_expect) {
+            Ok(replayed) => {
+                info!("found {} recorded requests", replayed.len());
+                replayed
+            }
+            Err(e) => return Err(format!("failed to load request log: {e}").into()),
+        },
+        _ => Vec::new(),
+    };
+
+    if testplans.is_empty() && replayed.is_empty() {
+        return Err(Box::new(NoTestplans));
     }
 
-    let mut success_count = 0;
-    let mut fail_count = 0;
+    let vars = Vars::default();
 
-    for file_path in testplans {
-        info!("processing {}...", file_path.display());
-        match TestPlan::load(&file_path.to_string_lossy()).and_then(|plan| {
-            let mut engine = TestEngine::new(plan);
-            engine.execute()
-        }) {
-            Ok(_) => {
-                info!("testplan succeeded");
-                success_count += 1;
+    if args.dry_run {
+        for (file_path, plan) in &testplans {
+            match plan {
+                Ok(plan) => println!("{}", TestEngine::new(plan.clone(), settings, &vars)),
+                Err(e) => error!("failed to load {}: {}", file_path.display(), e),
             }
-            Err(e) => {
-                error!("testplan failed: {}", e);
-                fail_count += 1;
+        }
+        for (_, plan) in replayed {
+            println!("{}", TestEngine::new(plan, settings, &vars));
+        }
+        return Ok(0);
+    }
+
+    let started = Instant::now();
+    let cache = settings.skip_unchanged.then(|| Mutex::new(Cache::load()));
+    let mut results = Vec::new();
+    let tap = args.format == Format::Tap;
+    if tap {
+        println!("1..{}", testplans.len() + replayed.len());
+    }
+
+    // set on the first failure with --fail-fast
+    let aborted = AtomicBool::new(false);
+    let abort = args.fail_fast.then_some(&aborted);
+    // names of the testplans, which haven't passed, so their dependents are skipped
+    let unpassed = Mutex::new(HashSet::new());
+
+    // testplans of the same priority are independent of each other, so they might run concurrently
+    let jobs = args.jobs.max(1);
+    let mut testplans = testplans.into_iter().peekable();
+    while let Some(first) = testplans.next() {
+        let group_priority = priority(&first.1);
+        let mut group = vec![first];
+        // dependents run after their dependencies, so they never share a group
+        while let Some(next) = testplans
+            .next_if(|(_, plan)| priority(plan) == group_priority && !depends_on_any(plan, &group))
+        {
+            group.push(next);
+        }
+        for result in run_group(
+            group,
+            jobs,
+            settings,
+            &vars,
+            cache.as_ref(),
+            abort,
+            &unpassed,
+        ) {
+            if tap {
+                print!(
+                    "{}",
+                    report::tap(results.len() + 1, &result, &result.secrets)
+                );
             }
+            results.push(result);
+        }
+    }
+
+    for (label, plan) in replayed {
+        let result = if aborted.load(Ordering::Relaxed) {
+            PlanResult::skipped(label, ABORTED)
+        } else {
+            info!("replaying {}...", label);
+            let result = run_testplan(label, Ok(plan), settings, &vars, None);
+            match &result.error {
+                None => info!("replay succeeded"),
+                Some(e) => error!(
+                    "replay of {} failed: {}",
+                    result.name,
+                    report::colorize(&report::mask(e, &result.secrets), settings.color)
+                ),
+            }
+            if let Some(abort) = abort
+                && result.failed()
+            {
+                abort.store(true, Ordering::Relaxed);
+            }
+            result
+        };
+        if tap {
+            print!(
+                "{}",
+                report::tap(results.len() + 1, &result, &result.secrets)
+            );
+        }
+        results.push(result);
+    }
+
+    // token, credentials and sensitive headers of all the testplans
+    let mut secrets: Vec<String> = settings.token.iter().cloned().collect();
+    for secret in results.iter().flat_map(|result| &result.secrets) {
+        if !secrets.contains(secret) {
+            secrets.push(secret.clone());
         }
     }
 
+    if let Some(cache) = cache
+        && let Err(e) = cache
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner)
+            .save()
+    {
+        error!("failed to save cache: {}", e);
+    }
+
+    if let Some(path) = &args.report_md {
+        match report::write_markdown(path, &results, &secrets) {
+            Ok(_) => info!("report is written to {}", path.display()),
+            Err(e) => error!("failed to write report: {}", e),
+        }
+    }
+    if let Some(path) = &args.report_junit {
+        match report::write_junit(path, &results, &secrets) {
+            Ok(_) => info!("JUnit report is written to {}", path.display()),
+            Err(e) => error!("failed to write JUnit report: {}", e),
+        }
+    }
+    if let Some(path) = &args.report_html {
+        match report::write_html(path, &results, &secrets) {
+            Ok(_) => info!("HTML report is written to {}", path.display()),
+            Err(e) => error!("failed to write HTML report: {}", e),
+        }
+    }
+
+    if args.format == Format::Json {
+        println!("{}", report::json(&results, &secrets));
+    }
+
+    let success_count = results.iter().filter(|result| result.passed).count();
+    let skip_count = results.iter().filter(|result| result.skipped).count();
+    let fail_count = results.len() - success_count - skip_count;
     info!(
-        "test execution completed, success: {}, failed: {}",
-        success_count, fail_count
+        "test execution completed in {} ms, success: {}, failed: {}, skipped: {}",
+        started.elapsed().as_millis(),
+        success_count,
+        fail_count,
+        skip_count
     );
-    Ok(())
+    let mut slowest: Vec<&PlanResult> = results.iter().filter(|result| !result.skipped).collect();
+    slowest.sort_by_key(|result| Reverse(result.duration_ms));
+    debug!("testplans by duration, slowest first:");
+    for result in slowest {
+        debug!("  {:>6} ms  {}", result.duration_ms, result.name);
+    }
+    Ok(fail_count)
+}
+
+// re-runs testplans on changes within the given paths until Ctrl-C: the changed testplans,
+// or all of them on change of any other file (i.e. schema or fixture)
+fn watch(args: &Args, settings: &Settings) -> Result<(), Box<dyn Error>> {
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    for path in &args.paths {
+        watcher.watch(path, RecursiveMode::Recursive)?;
+    }
+    loop {
+        info!("watching for changes, press Ctrl-C to stop...");
+        let mut changed = changed_paths(rx.recv()?);
+        // editors write files in several steps, so successive events are debounced
+        while let Ok(event) = rx.recv_timeout(WATCH_DEBOUNCE) {
+            changed.extend(changed_paths(event));
+        }
+        if changed.is_empty() {
+            continue;
+        }
+
+        let paths: Vec<PathBuf> = if changed.iter().all(|path| is_testplan(path)) {
+            changed.into_iter().collect()
+        } else {
+            args.paths.clone()
+        };
+        if let Err(e) = run(args, &paths, settings, false) {
+            error!("{}", e);
+        }
+        // files written by the run itself (i.e. `save_to`) don't trigger the next one
+        while rx.recv_timeout(WATCH_DEBOUNCE).is_ok() {}
+    }
+}
+
+// existing files, created or modified by the event
+fn changed_paths(event: notify::Result<Event>) -> BTreeSet<PathBuf> {
+    match event {
+        Ok(event) if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) => event
+            .paths
+            .into_iter()
+            .filter(|path| path.is_file())
+            .collect(),
+        Ok(_) => BTreeSet::new(),
+        Err(e) => {
+            error!("watch error: {}", e);
+            BTreeSet::new()
+        }
+    }
+}
+
+fn is_testplan(path: &Path) -> bool {
+    path.is_file() && path.extension().is_some_and(|ext| ext == "toml")
+}
+
+// path of the testplan, along with the data row or matrix entry (if any)
+fn label(path: &Path, plan: &Result<TestPlan, String>) -> String {
+    match plan.as_ref().ok().and_then(|plan| plan.row.as_deref()) {
+        Some(row) => format!("{} [{row}]", path.display()),
+        None => path.to_string_lossy().to_string(),
+    }
+}
+
+fn priority(plan: &Result<TestPlan, String>) -> i32 {
+    plan.as_ref().map_or(0, |plan| plan.plan.priority)
+}
+
+// whether the testplan depends on any testplan of the group
+fn depends_on_any(plan: &Result<TestPlan, String>, group: &[Loaded]) -> bool {
+    let Ok(plan) = plan else {
+        return false;
+    };
+    plan.plan.depends_on.iter().flatten().any(|dependency| {
+        group.iter().any(|(_, other)| {
+            other
+                .as_ref()
+                .is_ok_and(|other| other.plan_name() == dependency.as_str())
+        })
+    })
+}
+
+// moves dependencies before their dependents, keeping the order of the rest;
+// dependencies, which aren't run (i.e. not selected by tags), are ignored
+fn order_by_dependencies(testplans: Vec<Loaded>) -> Result<Vec<Loaded>, Box<dyn Error>> {
+    let names: Vec<Option<&str>> = testplans
+        .iter()
+        .map(|(_, plan)| plan.as_ref().ok().map(TestPlan::plan_name))
+        .collect();
+    let dependencies: Vec<Vec<usize>> = testplans
+        .iter()
+        .enumerate()
+        .map(|(index, (path, plan))| {
+            let Ok(plan) = plan else {
+                return Vec::new();
+            };
+            let mut indexes = Vec::new();
+            for dependency in plan.plan.depends_on.iter().flatten() {
+                // data rows and matrix entries of the testplan don't depend on each other
+                let found: Vec<usize> = (0..names.len())
+                    .filter(|&other| {
+                        names[other] == Some(dependency.as_str()) && names[other] != names[index]
+                    })
+                    .collect();
+                if found.is_empty() {
+                    warn!("dependency {} of {} is not run", dependency, path.display());
+                }
+                indexes.extend(found);
+            }
+            indexes
+        })
+        .collect();
+
+    let mut done = vec![false; testplans.len()];
+    let mut order = Vec::with_capacity(testplans.len());
+    while order.len() < testplans.len() {
+        // the first pending testplan, whose dependencies are all ordered
+        let ready = (0..testplans.len())
+            .find(|&index| !done[index] && dependencies[index].iter().all(|&dep| done[dep]));
+        match ready {
+            Some(index) => {
+                done[index] = true;
+                order.push(index);
+            }
+            None => {
+                let cycle: Vec<String> = (0..testplans.len())
+                    .filter(|&index| !done[index])
+                    .map(|index| testplans[index].0.display().to_string())
+                    .collect();
+                return Err(format!("dependency cycle among: {}", cycle.join(", ")).into());
+            }
+        }
+    }
+
+    let mut testplans: Vec<_> = testplans.into_iter().map(Some).collect();
+    Ok(order
+        .into_iter()
+        .filter_map(|index| testplans[index].take())
+        .collect())
+}
+
+// runs testplans on up to `jobs` threads, results are in the order of testplans;
+// once `abort` is set (by the failure of this or any previous group), the rest are skipped,
+// as well as the ones, whose dependencies are `unpassed`
+fn run_group(
+    group: Vec<Loaded>,
+    jobs: usize,
+    settings: &Settings,
+    vars: &Vars,
+    cache: Option<&Mutex<Cache>>,
+    abort: Option<&AtomicBool>,
+    unpassed: &Mutex<HashSet<String>>,
+) -> Vec<PlanResult> {
+    let workers = jobs.min(group.len());
+    let queue = Mutex::new(group.into_iter().enumerate());
+    let results = Mutex::new(Vec::new());
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| {
+                loop {
+                    let next = queue.lock().unwrap_or_else(PoisonError::into_inner).next();
+                    let Some((index, (file_path, plan))) = next else {
+                        break;
+                    };
+                    let name = label(&file_path, &plan);
+                    let plan_name = plan.as_ref().ok().map(|plan| plan.plan_name().to_string());
+                    let unpassed_dependency = plan.as_ref().ok().and_then(|plan| {
+                        let unpassed = unpassed.lock().unwrap_or_else(PoisonError::into_inner);
+                        plan.plan
+                            .depends_on
+                            .iter()
+                            .flatten()
+                            .find(|dependency| unpassed.contains(*dependency))
+                            .cloned()
+                    });
+                    let result = if abort.is_some_and(|abort| abort.load(Ordering::Relaxed)) {
+                        PlanResult::skipped(name, ABORTED)
+                    } else if let Some(dependency) = unpassed_dependency {
+                        let reason = format!("dependency {dependency} has not passed");
+                        warn!("testplan {} is skipped: {}", name, reason);
+                        PlanResult::skipped(name, &reason)
+                    } else {
+                        info!("processing {}...", name);
+                        let result = run_testplan(name, plan, settings, vars, cache);
+                        match &result.error {
+                            None if result.skipped => warn!(
+                                "testplan {} is skipped: {}",
+                                result.name,
+                                result.skip_reason.as_deref().unwrap_or_default()
+                            ),
+                            None => info!(
+                                "testplan {} succeeded in {} ms",
+                                result.name, result.duration_ms
+                            ),
+                            Some(e) => error!(
+                                "testplan {} failed: {}",
+                                result.name,
+                                report::colorize(&report::mask(e, &result.secrets), settings.color,)
+                            ),
+                        }
+                        if let Some(abort) = abort
+                            && result.failed()
+                        {
+                            abort.store(true, Ordering::Relaxed);
+                        }
+                        result
+                    };
+                    if !result.passed
+                        && let Some(plan_name) = plan_name
+                    {
+                        unpassed
+                            .lock()
+                            .unwrap_or_else(PoisonError::into_inner)
+                            .insert(plan_name);
+                    }
+                    results
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .push((index, result));
+                }
+            });
+        }
+    });
+
+    let mut results = results.into_inner().unwrap_or_else(PoisonError::into_inner);
+    results.sort_by_key(|(index, _)| *index);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+// runs a single testplan, using the fingerprint cache (if any) to skip unchanged responses
+fn run_testplan(
+    name: String,
+    plan: Result<TestPlan, String>,
+    settings: &Settings,
+    vars: &Vars,
+    cache: Option<&Mutex<Cache>>,
+) -> PlanResult {
+    let mut result = PlanResult {
+        name,
+        secrets: settings.token.iter().cloned().collect(),
+        ..Default::default()
+    };
+    let plan = match plan {
+        Ok(plan) => plan,
+        Err(e) => {
+            result.error = Some(e);
+            return result;
+        }
+    };
+
+    let started = Instant::now();
+    let mut engine = TestEngine::new(plan, settings, vars);
+    match engine.skip_reason() {
+        Ok(Some(reason)) => return PlanResult::skipped(result.name, &reason),
+        Ok(None) => {}
+        Err(e) => {
+            result.error = Some(e.to_string());
+            return result;
+        }
+    }
+    if let Some(cache) = cache {
+        let cache = cache.lock().unwrap_or_else(PoisonError::into_inner);
+        engine.set_cached_fingerprint(cache.get(&result.name));
+    }
+    let outcome = engine.execute();
+    result.duration_ms = started.elapsed().as_millis();
+    result.request = Some(engine.request_line());
+    result.secrets = engine.secrets();
+    if let Some(response) = engine.last_response() {
+        result.status = Some(response.status);
+        result.response = Some(response.body.clone());
+    }
+    result.assertions = engine.assertions().to_vec();
+
+    match outcome {
+        Ok(_) => {
+            result.passed = true;
+            if let (Some(cache), Some(fingerprint)) = (cache, engine.fingerprint()) {
+                cache
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .insert(result.name.clone(), fingerprint);
+            }
+        }
+        Err(e) => result.error = Some(e.to_string()),
+    }
+    result
+}
+
+fn resolve_settings(args: &Args) -> Result<Settings, Box<dyn Error>> {
+    let cli = Layer {
+        base_url: args.url.clone(),
+        base_path: args.base_path.clone(),
+        token: args.token.clone(),
+        token_command: None,
+        timeout: args.timeout,
+    };
+    let mut settings = config::resolve(cli, args.config.as_deref(), args.profile.as_deref())?;
+    settings.json_pointer_errors = args.json_pointer_errors;
+    settings.skip_unchanged = args.skip_unchanged;
+    settings.explain = args.explain;
+    if let Some(path) = &args.env_file {
+        settings.env_file = config::load_env_file(path)?;
+        info!(
+            "loaded {} vars from {}",
+            settings.env_file.len(),
+            path.display()
+        );
+    }
+    settings.env_file_override = args.env_file_override;
+    settings.seed = args.seed;
+    // diffs are colored in the log, which goes to stderr
+    settings.color = !args.no_color && args.format == Format::Log && io::stderr().is_terminal();
+    Ok(settings)
 }
 
 fn collect_testplans(path: PathBuf, testplans: &mut Vec<PathBuf>) -> Result<(), io::Error> {
-    if path.is_file() && path.extension().map_or(false, |ext| ext == "toml") {
+    if is_testplan(&path) {
         testplans.push(path);
     } else if path.is_dir() {
         for entry in fs::read_dir(path)? {
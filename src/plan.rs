@@ -1,52 +1,396 @@
+use indexmap::IndexMap;
 use serde::Deserialize;
-use std::{collections::HashMap, fmt, fs};
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+};
 
-#[derive(Deserialize, Debug, Default)]
+#[derive(Deserialize, Debug, Default, Clone)]
 pub struct TestPlan {
-    #[serde(rename = "in")]
+    // file stem of the testplan
+    #[serde(skip)]
+    pub name: String,
+    // directory of the testplan file, relative paths within testplan are resolved against it
+    #[serde(skip)]
+    pub dir: PathBuf,
+    // hash of the testplan file content
+    #[serde(skip)]
+    pub source_hash: u64,
+    // label of the data row of `data_file` (`row 1`), or of the `matrix` entry (`id=5, size=10`),
+    // whose vars are substituted
+    #[serde(skip)]
+    pub row: Option<String>,
+    #[serde(skip)]
+    pub row_vars: HashMap<String, String>,
+    // single request testplan, unused when `step`s are given
+    #[serde(rename = "in", default)]
     pub input: Input,
     #[serde(default)]
     pub plan: crate::plan::Plan,
-    #[serde(rename = "out")]
+    #[serde(rename = "out", default)]
+    pub output: Output,
+    // multi-step testplan: requests, executed in declaration order
+    #[serde(rename = "step", default)]
+    pub steps: Vec<Step>,
+    // credentials of the requests, instead of the global token
+    #[serde(default)]
+    pub auth: Option<Auth>,
+    // requests before and after the main one(s), i.e. seeding and cleaning up the data;
+    // teardown is attempted even if the main request fails
+    #[serde(default)]
+    pub setup: Option<Step>,
+    #[serde(default)]
+    pub teardown: Option<Step>,
+    // parameterized testplan: executed once per entry of var assignments
+    #[serde(default)]
+    pub matrix: Vec<IndexMap<String, toml::Value>>,
+    // testplan-local constants, values might reference other vars (and preceding entries)
+    #[serde(default)]
+    pub vars: IndexMap<String, toml::Value>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct Step {
+    #[serde(rename = "in")]
+    pub input: Input,
+    #[serde(rename = "out", default)]
     pub output: Output,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Auth {
+    // HTTP Basic, vars are substituted in both
+    Basic {
+        username: String,
+        password: String,
+    },
+    // `Authorization: Bearer <token>`, the global token by default
+    Bearer {
+        token: Option<String>,
+        header: Option<String>,
+    },
+    // key as is, i.e. `X-Api-Key: <key>`
+    ApiKey {
+        key: String,
+        header: Option<String>,
+    },
+}
+
+#[derive(Deserialize, Debug, Clone)]
 pub struct Plan {
+    #[serde(default = "default_executor")]
     pub executor: String,
+    // higher priority testplans are executed first
+    #[serde(default)]
+    pub priority: i32,
+    // sends the request twice, expecting the same responses
+    #[serde(default)]
+    pub assert_idempotent: bool,
+    // resolver overrides: comma-separated DNS servers, DNS-over-HTTPS endpoint
+    #[serde(default)]
+    pub dns_servers: Option<String>,
+    #[serde(default)]
+    pub doh_url: Option<String>,
+    // static resolver overrides, `host:port:address` entries (i.e. `--resolve` of curl)
+    #[serde(default)]
+    pub resolve: Vec<String>,
+    // extra arguments of curl executor, appended verbatim (i.e. `--compressed`, `--http1.1`)
+    #[serde(default)]
+    pub curl_args: Option<Vec<String>>,
+    // re-attempts of the request on connection errors (and `retry_statuses`), not on mismatches
+    #[serde(default)]
+    pub retries: Option<u32>,
+    #[serde(default)]
+    pub retry_delay_ms: Option<u64>,
+    #[serde(default)]
+    pub retry_statuses: Vec<u16>,
+    // groups of the testplan (`smoke`, `regression`, ...), selected with `--tag`
+    #[serde(default)]
+    pub tags: Option<Vec<String>>,
+    // redirects are not followed by default, at most `max_redirects` (10 by default) otherwise
+    #[serde(default)]
+    pub follow_redirects: Option<bool>,
+    #[serde(default)]
+    pub max_redirects: Option<usize>,
+    // skips TLS certificate verification, i.e. of self-signed staging servers
+    #[serde(default)]
+    pub insecure: Option<bool>,
+    // CSV file (relative to the testplan), the testplan is executed once per its data row,
+    // with the columns of the header row as vars
+    #[serde(default)]
+    pub data_file: Option<PathBuf>,
+    // proxy URL (vars are substituted), overrides http_proxy/HTTPS_PROXY env vars
+    #[serde(default)]
+    pub proxy: Option<String>,
+    // `[vars]` take precedence over process env and assigned vars, instead of the other way round
+    #[serde(default)]
+    pub vars_override: bool,
+    // names of the testplans, which are run first, and must pass for this one to run
+    #[serde(default)]
+    pub depends_on: Option<Vec<String>>,
+    // condition of skipping the testplan: `<left> == <right>` or `<left> != <right>`,
+    // vars are substituted in both sides, i.e. `$ENV == prod`
+    #[serde(default)]
+    pub skip_if: Option<String>,
+    // cookies of `Set-Cookie` response headers are sent with the later requests of the testplan
+    #[serde(default)]
+    pub cookies: bool,
+    // large JSON response of `http` executor is parsed while received, keeping only the fields
+    // of `[out.expect]` and `[out.assign]`
+    #[serde(default)]
+    pub stream: bool,
+    // base URL of the testplan (i.e. of another service), overrides the global one
+    #[serde(default)]
+    pub base_url: Option<String>,
+    // `false` for plain REST responses, validated from the root without code and data fields
+    #[serde(default = "default_envelope")]
+    pub envelope: bool,
+    // response envelope: names of its fields and the code of success
+    #[serde(default = "default_code_field")]
+    pub code_field: String,
+    #[serde(default = "default_data_field")]
+    pub data_field: String,
+    #[serde(default = "default_success_code")]
+    pub success_code: serde_json::Value,
+    // name of the testplan to run it alone with `--name`, file stem by default
+    #[serde(default)]
+    pub name: Option<String>,
 }
 
 impl Default for crate::plan::Plan {
     fn default() -> Self {
         Self {
-            executor: "curl".to_string(),
+            executor: default_executor(),
+            priority: 0,
+            assert_idempotent: false,
+            dns_servers: None,
+            doh_url: None,
+            resolve: Vec::new(),
+            curl_args: None,
+            retries: None,
+            retry_delay_ms: None,
+            retry_statuses: Vec::new(),
+            tags: None,
+            name: None,
+            follow_redirects: None,
+            max_redirects: None,
+            insecure: None,
+            data_file: None,
+            proxy: None,
+            vars_override: false,
+            depends_on: None,
+            skip_if: None,
+            cookies: false,
+            stream: false,
+            base_url: None,
+            envelope: default_envelope(),
+            code_field: default_code_field(),
+            data_field: default_data_field(),
+            success_code: default_success_code(),
         }
     }
 }
 
-#[derive(Deserialize, Debug, Default)]
+fn default_executor() -> String {
+    "curl".to_string()
+}
+
+fn default_envelope() -> bool {
+    true
+}
+
+fn default_code_field() -> String {
+    "code".to_string()
+}
+
+fn default_data_field() -> String {
+    "data".to_string()
+}
+
+fn default_success_code() -> serde_json::Value {
+    serde_json::Value::from(0)
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
 pub struct Input {
     #[serde(default = "default_method")]
     pub method: Option<String>,
     pub json: Option<String>,
+    // name of env var, holding JSON request body (alternative to `json`)
+    #[serde(default)]
+    pub json_env: Option<String>,
+    // file with JSON request body, relative to the testplan (alternative to `json`)
+    #[serde(default)]
+    pub json_file: Option<PathBuf>,
+    // type of the body: json (default), form (`form` fields), text (raw `text`)
+    #[serde(default)]
+    pub body_type: Option<String>,
+    #[serde(default)]
+    pub form: IndexMap<String, String>,
+    #[serde(default)]
+    pub text: Option<String>,
+    // files to upload by field names, relative to the testplan; `form` fields go along with them
+    #[serde(default)]
+    pub files: IndexMap<String, PathBuf>,
     pub url: String,
+    // query params, appended to `url` in declaration order, percent-encoded
+    #[serde(default)]
+    pub query: IndexMap<String, String>,
+    // extra request headers, both names and values are subject to var substitution
+    #[serde(default)]
+    pub headers: IndexMap<String, String>,
+    // request timeout, in milliseconds, overrides the global one
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+    // `url` and `json` are sent as is, without var substitution (i.e. of replayed requests)
+    #[serde(skip)]
+    pub literal: bool,
 }
 
 fn default_method() -> Option<String> {
     Some("GET".to_string())
 }
 
-#[derive(Deserialize, Debug, Default)]
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum Status {
+    Code(u16),
+    Pattern(String),
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Status::Code(code) => write!(f, "{code}"),
+            Status::Pattern(pattern) => f.write_str(pattern),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
 pub struct Output {
-    pub expect: HashMap<String, String>,
+    // expected fields of the response, which might be omitted along with `expect_body` or `schema`
+    #[serde(default)]
+    pub expect: IndexMap<String, String>,
+    // expected HTTP status, either exact (`404`), or with `x` wildcards (`"2xx"`)
+    #[serde(default)]
+    pub status: Option<Status>,
+    // expected response headers, names are case-insensitive
+    #[serde(default)]
+    pub headers: IndexMap<String, String>,
+    // assignments are applied in declaration order, so later ones may rely on earlier ones
     #[serde(default)]
-    pub assign: Option<HashMap<String, String>>,
+    pub assign: Option<IndexMap<String, String>>,
+    // whole expected JSON body, inline or from env var
+    #[serde(default)]
+    pub expect_body: Option<String>,
+    #[serde(default)]
+    pub expect_body_env: Option<String>,
+    // JSON Schema file of the whole response, regardless of HTTP status
+    #[serde(default)]
+    pub schema: Option<String>,
+    // JSON Schema files by HTTP status pattern (`200`, `2xx`, `4xx`), first match wins
+    #[serde(default)]
+    pub schemas: IndexMap<String, String>,
+    // namespace of assigned vars (`$namespace.VAR`), file stem of the testplan by default
+    #[serde(default)]
+    pub namespace: Option<String>,
+    // file to save the body of passed response to, relative to the testplan, vars are substituted
+    #[serde(default)]
+    pub save_to: Option<PathBuf>,
+    #[serde(default)]
+    pub min_response_ms: Option<u64>,
+    #[serde(default)]
+    pub max_response_ms: Option<u64>,
 }
 
 impl TestPlan {
     pub fn load(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
         let content = fs::read_to_string(path)?;
-        Ok(toml::from_str(&content)?)
+        let mut plan: TestPlan = toml::from_str(&content)?;
+        if plan.steps.is_empty() && plan.input.url.is_empty() {
+            return Err("either [in] section or [[step]]s are required".into());
+        }
+        let path = Path::new(path);
+        plan.name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+        plan.dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        plan.source_hash = crate::cache::hash_source(&content);
+        Ok(plan)
+    }
+
+    /// Explicit name of the testplan, or its file stem.
+    pub fn plan_name(&self) -> &str {
+        self.plan.name.as_deref().unwrap_or(&self.name)
+    }
+
+    /// Copies of the testplan, one per data row of `data_file` or per `matrix` entry,
+    /// or the testplan itself.
+    pub fn variants(self) -> Result<Vec<Self>, Box<dyn std::error::Error>> {
+        if !self.matrix.is_empty() {
+            if self.plan.data_file.is_some() {
+                return Err("both 'data_file' and [[matrix]] are set".into());
+            }
+            return Ok(self.matrix_entries());
+        }
+        let Some(data_file) = &self.plan.data_file else {
+            return Ok(vec![self]);
+        };
+        let path = self.dir.join(data_file);
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_path(&path)
+            .map_err(|e| format!("failed to read {}: {e}", path.display()))?;
+        let header = reader.headers()?.clone();
+        let mut rows = Vec::new();
+        for (index, record) in reader.records().enumerate() {
+            let record = record?;
+            let mut plan = self.clone();
+            plan.row = Some(format!("row {}", index + 1));
+            plan.row_vars = header
+                .iter()
+                .zip(record.iter())
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect();
+            rows.push(plan);
+        }
+        if rows.is_empty() {
+            return Err(format!("no data rows in {}", path.display()).into());
+        }
+        Ok(rows)
+    }
+
+    fn matrix_entries(&self) -> Vec<Self> {
+        self.matrix
+            .iter()
+            .map(|entry| {
+                let vars: Vec<(String, String)> = entry
+                    .iter()
+                    .map(|(name, value)| (name.clone(), var_value(value)))
+                    .collect();
+                let mut plan = self.clone();
+                plan.row = Some(
+                    vars.iter()
+                        .map(|(name, value)| format!("{name}={value}"))
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                );
+                plan.row_vars = vars.into_iter().collect();
+                plan
+            })
+            .collect()
+    }
+}
+
+/// Text of TOML value as var: strings are kept as is, the rest are in TOML notation.
+pub fn var_value(value: &toml::Value) -> String {
+    match value {
+        toml::Value::String(s) => s.clone(),
+        other => other.to_string(),
     }
 }
 
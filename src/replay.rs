@@ -0,0 +1,94 @@
+use indexmap::IndexMap;
+use serde::Deserialize;
+use serde_json::Value;
+use std::{error::Error, fs, path::Path};
+
+use crate::plan::{Input, Output, Plan, Status, TestPlan};
+
+// single recorded request, one JSON object per line of the log
+#[derive(Deserialize, Debug)]
+struct Record {
+    #[serde(default = "default_method")]
+    method: String,
+    path: String,
+    #[serde(default)]
+    body: Option<Value>,
+    // recorded HTTP status, any 2xx one is expected by default
+    #[serde(default)]
+    status: Option<u16>,
+}
+
+fn default_method() -> String {
+    "GET".to_string()
+}
+
+/// Loads a recorded request log and turns each record into an ephemeral testplan,
+/// sharing the same `expect`ations (`key=value` pairs) of the plain response (no envelope).
+/// Recorded paths and bodies are sent as is, without var substitution.
+pub fn load(path: &Path, expect: &[String]) -> Result<Vec<(String, TestPlan)>, Box<dyn Error>> {
+    let expect = parse_expect(expect)?;
+    let content = fs::read_to_string(path)?;
+
+    let mut testplans = Vec::new();
+    for (idx, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let record: Record = serde_json::from_str(line)
+            .map_err(|e| format!("{}:{}: invalid record: {e}", path.display(), idx + 1))?;
+        let label = format!(
+            "{}:{} {} {}",
+            path.display(),
+            idx + 1,
+            record.method,
+            record.path
+        );
+        let status = match record.status {
+            Some(status) => Status::Code(status),
+            None => Status::Pattern("2xx".to_string()),
+        };
+        let json = record.body.map(|body| match body {
+            Value::String(s) => s,
+            other => other.to_string(),
+        });
+        testplans.push((
+            label,
+            TestPlan {
+                plan: Plan {
+                    envelope: false,
+                    ..Default::default()
+                },
+                input: Input {
+                    method: Some(record.method.to_uppercase()),
+                    json,
+                    url: record.path,
+                    literal: true,
+                    ..Default::default()
+                },
+                output: Output {
+                    expect: expect.clone(),
+                    status: Some(status),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        ));
+    }
+    Ok(testplans)
+}
+
+fn parse_expect(expect: &[String]) -> Result<IndexMap<String, String>, Box<dyn Error>> {
+    expect
+        .iter()
+        .map(|pair| {
+            pair.split_once('=')
+                .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+                .ok_or_else(|| {
+                    Box::<dyn Error>::from(format!(
+                        "invalid expectation '{pair}', key=value expected"
+                    ))
+                })
+        })
+        .collect()
+}
@@ -0,0 +1,435 @@
+use serde_json::json;
+use std::{error::Error, fmt::Write, fs, path::Path};
+
+// max length of the response body, kept in reports
+const SNIPPET_LEN: usize = 500;
+
+// embedded stylesheet of HTML report, so it has no external assets
+const HTML_STYLE: &str = "
+body { font-family: sans-serif; margin: 2em; }
+table { border-collapse: collapse; width: 100%; }
+th, td { border: 1px solid #ddd; padding: 4px 8px; text-align: left; vertical-align: top; }
+tr.passed .status { color: #1a7f37; }
+tr.failed .status { color: #cf222e; }
+tr.skipped .status { color: #9a6700; }
+pre { margin: 0; white-space: pre-wrap; }
+.del { color: #cf222e; }
+.ins { color: #1a7f37; }
+.badge { color: #fff; padding: 2px 8px; border-radius: 4px; font-size: 0.6em; }
+.badge.passed { background: #1a7f37; }
+.badge.failed { background: #cf222e; }
+";
+
+/// Outcome of a single testplan.
+#[derive(Debug, Default)]
+pub struct PlanResult {
+    pub name: String,
+    pub passed: bool,
+    // not executed, i.e. as the run is aborted by the previous failure
+    pub skipped: bool,
+    pub skip_reason: Option<String>,
+    pub status: Option<u16>,
+    // method and URL of the (last) request
+    pub request: Option<String>,
+    pub duration_ms: u128,
+    pub error: Option<String>,
+    pub response: Option<String>,
+    pub assertions: Vec<Assertion>,
+    // token, credentials and sensitive headers of the request, masked in logs and reports
+    pub secrets: Vec<String>,
+}
+
+impl PlanResult {
+    pub fn skipped(name: String, reason: &str) -> Self {
+        PlanResult {
+            name,
+            skipped: true,
+            skip_reason: Some(reason.to_string()),
+            ..Default::default()
+        }
+    }
+
+    pub fn failed(&self) -> bool {
+        !self.passed && !self.skipped
+    }
+
+    pub fn outcome(&self) -> &'static str {
+        match (self.passed, self.skipped) {
+            (true, _) => "passed",
+            (false, true) => "skipped",
+            (false, false) => "failed",
+        }
+    }
+}
+
+/// Outcome of a single field expectation.
+#[derive(Debug, Clone)]
+pub struct Assertion {
+    pub field: String,
+    pub expected: String,
+    // none for absent field
+    pub actual: Option<String>,
+    pub passed: bool,
+    // failed soft assertion doesn't fail the testplan
+    pub soft: bool,
+}
+
+/// Writes Markdown summary (i.e. for PR comments), with `secrets` masked.
+pub fn write_markdown(
+    path: &Path,
+    results: &[PlanResult],
+    secrets: &[String],
+) -> Result<(), Box<dyn Error>> {
+    let passed = results.iter().filter(|result| result.passed).count();
+    let skipped = results.iter().filter(|result| result.skipped).count();
+    let mut md = String::new();
+    writeln!(md, "# {} report\n", env!("CARGO_PKG_NAME"))?;
+    writeln!(
+        md,
+        "**{}** testplans: **{}** passed, **{}** failed, **{}** skipped\n",
+        results.len(),
+        passed,
+        results.len() - passed - skipped,
+        skipped
+    )?;
+
+    writeln!(md, "| Testplan | Status | HTTP | Duration |")?;
+    writeln!(md, "|---|---|---|---|")?;
+    for result in results {
+        writeln!(
+            md,
+            "| `{}` | {} | {} | {} ms |",
+            result.name.replace('|', "\\|"),
+            match (result.passed, result.skipped) {
+                (true, _) => "✅ passed",
+                (false, true) => "⏭️ skipped",
+                (false, false) => "❌ failed",
+            },
+            result
+                .status
+                .map_or("-".to_string(), |status| status.to_string()),
+            result.duration_ms
+        )?;
+    }
+
+    let failures: Vec<&PlanResult> = results.iter().filter(|result| result.failed()).collect();
+    if !failures.is_empty() {
+        writeln!(md, "\n## Failures")?;
+    }
+    for result in failures {
+        writeln!(
+            md,
+            "\n<details>\n<summary><code>{}</code></summary>\n",
+            result.name
+        )?;
+        if let Some(error) = &result.error {
+            writeln!(md, "**Error:**\n```\n{}\n```\n", mask(error, secrets))?;
+        }
+        if let Some(response) = &result.response {
+            let snippet: String = response.chars().take(SNIPPET_LEN).collect();
+            writeln!(md, "**Response:**\n```\n{}\n```\n", mask(&snippet, secrets))?;
+        }
+        writeln!(md, "</details>")?;
+    }
+
+    let checked: Vec<&PlanResult> = results
+        .iter()
+        .filter(|result| !result.assertions.is_empty())
+        .collect();
+    if !checked.is_empty() {
+        writeln!(md, "\n## Assertions")?;
+    }
+    for result in checked {
+        writeln!(
+            md,
+            "\n<details>\n<summary><code>{}</code></summary>\n",
+            result.name
+        )?;
+        writeln!(md, "| Field | Expected | Actual | Status |")?;
+        writeln!(md, "|---|---|---|---|")?;
+        for assertion in &result.assertions {
+            writeln!(
+                md,
+                "| `{}` | `{}` | `{}` | {} |",
+                assertion.field.replace('|', "\\|"),
+                mask(&assertion.expected, secrets).replace('|', "\\|"),
+                mask(assertion.actual.as_deref().unwrap_or("<absent>"), secrets)
+                    .replace('|', "\\|"),
+                match (assertion.passed, assertion.soft) {
+                    (true, _) => "✅",
+                    (false, true) => "⚠️ soft",
+                    (false, false) => "❌",
+                }
+            )?;
+        }
+        writeln!(md, "\n</details>")?;
+    }
+
+    fs::write(path, md)?;
+    Ok(())
+}
+
+/// Writes JUnit XML report (i.e. for GitLab, Jenkins), with `secrets` masked.
+pub fn write_junit(
+    path: &Path,
+    results: &[PlanResult],
+    secrets: &[String],
+) -> Result<(), Box<dyn Error>> {
+    let failures = results.iter().filter(|result| result.failed()).count();
+    let skipped = results.iter().filter(|result| result.skipped).count();
+    let total_ms: u128 = results.iter().map(|result| result.duration_ms).sum();
+    let mut xml = String::new();
+    writeln!(xml, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        xml,
+        r#"<testsuites tests="{}" failures="{}" time="{}">"#,
+        results.len(),
+        failures,
+        seconds(total_ms)
+    )?;
+    writeln!(
+        xml,
+        r#"  <testsuite name="{}" tests="{}" failures="{}" skipped="{}" time="{}">"#,
+        env!("CARGO_PKG_NAME"),
+        results.len(),
+        failures,
+        skipped,
+        seconds(total_ms)
+    )?;
+    for result in results {
+        write!(
+            xml,
+            r#"    <testcase name="{}" classname="{}" time="{}""#,
+            xml_escape(&result.name),
+            env!("CARGO_PKG_NAME"),
+            seconds(result.duration_ms)
+        )?;
+        if result.passed {
+            writeln!(xml, "/>")?;
+            continue;
+        }
+        if result.skipped {
+            writeln!(xml, ">")?;
+            writeln!(
+                xml,
+                r#"      <skipped message="{}"/>"#,
+                xml_escape(result.skip_reason.as_deref().unwrap_or_default())
+            )?;
+            writeln!(xml, "    </testcase>")?;
+            continue;
+        }
+        let error = mask(result.error.as_deref().unwrap_or("failed"), secrets);
+        let message = error.lines().next().unwrap_or_default();
+        writeln!(xml, ">")?;
+        writeln!(
+            xml,
+            r#"      <failure message="{}">{}</failure>"#,
+            xml_escape(message),
+            xml_escape(&error)
+        )?;
+        if let Some(response) = &result.response {
+            let snippet: String = response.chars().take(SNIPPET_LEN).collect();
+            writeln!(
+                xml,
+                "      <system-out>{}</system-out>",
+                xml_escape(&mask(&snippet, secrets))
+            )?;
+        }
+        writeln!(xml, "    </testcase>")?;
+    }
+    writeln!(xml, "  </testsuite>")?;
+    writeln!(xml, "</testsuites>")?;
+
+    fs::write(path, xml)?;
+    Ok(())
+}
+
+/// Writes self-contained HTML page (i.e. for non-CLI stakeholders), with `secrets` masked.
+pub fn write_html(
+    path: &Path,
+    results: &[PlanResult],
+    secrets: &[String],
+) -> Result<(), Box<dyn Error>> {
+    let passed = results.iter().filter(|result| result.passed).count();
+    let skipped = results.iter().filter(|result| result.skipped).count();
+    let failed = results.len() - passed - skipped;
+    let outcome = if failed == 0 { "passed" } else { "failed" };
+    let mut html = String::new();
+    writeln!(
+        html,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">"
+    )?;
+    writeln!(html, "<title>{} report</title>", env!("CARGO_PKG_NAME"))?;
+    writeln!(html, "<style>{HTML_STYLE}</style>\n</head>\n<body>")?;
+    writeln!(
+        html,
+        r#"<h1>{} report <span class="badge {outcome}">{}</span></h1>"#,
+        env!("CARGO_PKG_NAME"),
+        outcome.to_uppercase()
+    )?;
+    writeln!(
+        html,
+        "<p><b>{}</b> testplans: <b>{}</b> passed, <b>{}</b> failed, <b>{}</b> skipped</p>",
+        results.len(),
+        passed,
+        failed,
+        skipped
+    )?;
+
+    writeln!(html, "<table>")?;
+    writeln!(
+        html,
+        "<tr><th>Testplan</th><th>Status</th><th>Request</th><th>HTTP</th><th>Duration</th></tr>"
+    )?;
+    for result in results {
+        let outcome = result.outcome();
+        write!(
+            html,
+            r#"<tr class="{outcome}"><td><code>{}</code></td><td class="status">{outcome}</td>"#,
+            xml_escape(&result.name)
+        )?;
+        writeln!(
+            html,
+            "<td><code>{}</code></td><td>{}</td><td>{} ms</td></tr>",
+            xml_escape(&mask(result.request.as_deref().unwrap_or("-"), secrets)),
+            result
+                .status
+                .map_or("-".to_string(), |status| status.to_string()),
+            result.duration_ms
+        )?;
+        let details = match (&result.error, &result.skip_reason) {
+            (Some(error), _) => html_diff(&mask(error, secrets)),
+            (None, Some(reason)) => xml_escape(reason),
+            (None, None) => continue,
+        };
+        write!(
+            html,
+            r#"<tr class="{outcome}"><td colspan="5"><pre>{details}</pre>"#
+        )?;
+        if let Some(response) = result.response.as_ref().filter(|_| result.failed()) {
+            let snippet: String = response.chars().take(SNIPPET_LEN).collect();
+            write!(
+                html,
+                "<details><summary>Response</summary><pre>{}</pre></details>",
+                xml_escape(&mask(&snippet, secrets))
+            )?;
+        }
+        writeln!(html, "</td></tr>")?;
+    }
+    writeln!(html, "</table>\n</body>\n</html>")?;
+
+    fs::write(path, html)?;
+    Ok(())
+}
+
+/// TAP line of the `number`th testplan, with YAML diagnostics of the failure.
+pub fn tap(number: usize, result: &PlanResult, secrets: &[String]) -> String {
+    let name = tap_escape(&result.name);
+    if result.passed {
+        return format!("ok {number} - {name}\n");
+    }
+    if result.skipped {
+        return format!(
+            "ok {number} - {name} # SKIP {}\n",
+            result.skip_reason.as_deref().unwrap_or_default()
+        );
+    }
+    let error = mask(result.error.as_deref().unwrap_or("failed"), secrets);
+    // JSON string is a valid YAML one, with all the escaping done
+    format!(
+        "not ok {number} - {name}\n  ---\n  message: {}\n  duration_ms: {}\n  ...\n",
+        serde_json::to_string(&error).unwrap_or_default(),
+        result.duration_ms
+    )
+}
+
+/// JSON document of the whole run (per testplan results and totals), with `secrets` masked.
+pub fn json(results: &[PlanResult], secrets: &[String]) -> String {
+    let passed = results.iter().filter(|result| result.passed).count();
+    let skipped = results.iter().filter(|result| result.skipped).count();
+    let plans: Vec<serde_json::Value> = results
+        .iter()
+        .map(|result| {
+            json!({
+                "path": result.name,
+                "status": result.outcome(),
+                "http_status": result.status,
+                "error": result.error.as_deref().map(|error| mask(error, secrets)),
+                "duration_ms": result.duration_ms,
+            })
+        })
+        .collect();
+    let report = json!({
+        "results": plans,
+        "totals": {
+            "total": results.len(),
+            "passed": passed,
+            "failed": results.len() - passed - skipped,
+            "skipped": skipped,
+            "duration_ms": results.iter().map(|result| result.duration_ms).sum::<u128>(),
+        },
+    });
+    serde_json::to_string_pretty(&report).unwrap_or_default()
+}
+
+/// Colors `-` (expected) lines of the diffs within `text` in red, and `+` (actual) in green.
+pub fn colorize(text: &str, color: bool) -> String {
+    if !color {
+        return text.to_string();
+    }
+    text.lines()
+        .map(|line| {
+            if line.starts_with("  - ") {
+                format!("\x1b[31m{line}\x1b[0m")
+            } else if line.starts_with("  + ") {
+                format!("\x1b[32m{line}\x1b[0m")
+            } else {
+                line.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// escaped `text`, with `-` (expected) and `+` (actual) lines of the diffs marked, as by `colorize`
+fn html_diff(text: &str) -> String {
+    text.lines()
+        .map(|line| {
+            let escaped = xml_escape(line);
+            if line.starts_with("  - ") {
+                format!(r#"<span class="del">{escaped}</span>"#)
+            } else if line.starts_with("  + ") {
+                format!(r#"<span class="ins">{escaped}</span>"#)
+            } else {
+                escaped
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn seconds(ms: u128) -> String {
+    format!("{:.3}", ms as f64 / 1000.0)
+}
+
+// `#` of the description would start a directive, i.e. `# SKIP`
+fn tap_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('#', "\\#")
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+pub fn mask(text: &str, secrets: &[String]) -> String {
+    secrets
+        .iter()
+        .filter(|secret| !secret.is_empty())
+        .fold(text.to_string(), |text, secret| {
+            text.replace(secret.as_str(), "***")
+        })
+}
@@ -0,0 +1,80 @@
+use std::fmt::Write;
+
+// headers, whose values are never printed as is
+const SENSITIVE_HEADERS: [&str; 5] = [
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+    "x-api-key",
+];
+
+#[derive(Debug, Default, Clone)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl Response {
+    /// Parses the output of `curl -i`: one or more header blocks (i.e. after redirects
+    /// or `100 Continue`), followed by the body. The last header block wins.
+    pub fn parse(raw: &str) -> Self {
+        let mut response = Response::default();
+        let mut rest = raw;
+        while rest.starts_with("HTTP/") {
+            let Some((head, body)) = rest
+                .split_once("\r\n\r\n")
+                .or_else(|| rest.split_once("\n\n"))
+            else {
+                break;
+            };
+            let mut lines = head.lines();
+            response.status = lines
+                .next()
+                .and_then(|status_line| status_line.split_whitespace().nth(1))
+                .and_then(|code| code.parse().ok())
+                .unwrap_or_default();
+            response.headers = lines
+                .filter_map(|line| line.split_once(':'))
+                .map(|(name, value)| (name.trim().to_lowercase(), value.trim().to_string()))
+                .collect();
+            rest = body;
+        }
+        response.body = rest.to_string();
+        response
+    }
+
+    /// Returns all values of the header (case-insensitive), joined with ", ".
+    pub fn header(&self, name: &str) -> Option<String> {
+        let name = name.to_lowercase();
+        let values: Vec<&str> = self
+            .headers
+            .iter()
+            .filter(|(n, _)| *n == name)
+            .map(|(_, v)| v.as_str())
+            .collect();
+        if values.is_empty() {
+            None
+        } else {
+            Some(values.join(", "))
+        }
+    }
+
+    /// Dumps all received headers, one per line, with sensitive values masked.
+    pub fn masked_headers(&self) -> String {
+        let mut dump = String::new();
+        for (name, value) in &self.headers {
+            let _ = writeln!(dump, "  {name}: {}", mask_header(name, value));
+        }
+        dump
+    }
+}
+
+pub fn mask_header<'a>(name: &str, value: &'a str) -> &'a str {
+    if SENSITIVE_HEADERS.contains(&name.to_lowercase().as_str()) {
+        "***"
+    } else {
+        value
+    }
+}
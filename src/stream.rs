@@ -0,0 +1,125 @@
+use serde::de::{
+    Deserialize, DeserializeSeed, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor,
+};
+use serde_json::{Map, Value};
+use std::{collections::HashMap, fmt, io::Read};
+
+/// Part of JSON document to keep: the whole value, or only the given fields (or array items).
+#[derive(Debug)]
+pub enum Keep {
+    All,
+    Fields(HashMap<String, Keep>),
+}
+
+impl Keep {
+    pub fn fields() -> Self {
+        Keep::Fields(HashMap::new())
+    }
+
+    /// Keeps the whole value of the dot-separated path (numeric segments are array indexes).
+    pub fn insert(&mut self, path: &str) {
+        // the whole value is kept already
+        let Keep::Fields(fields) = self else {
+            return;
+        };
+        // the path might be a plain key, containing dots
+        fields.insert(path.to_string(), Keep::All);
+        if let Some((key, rest)) = path.split_once('.') {
+            fields
+                .entry(key.to_string())
+                .or_insert_with(Keep::fields)
+                .insert(rest);
+        }
+    }
+}
+
+/// Parses JSON document incrementally from the reader, skipping the values, which aren't kept.
+pub fn parse(reader: impl Read, keep: &Keep) -> serde_json::Result<Value> {
+    let mut deserializer = serde_json::Deserializer::from_reader(reader);
+    let value = Pruned(keep).deserialize(&mut deserializer)?;
+    deserializer.end()?;
+    Ok(value)
+}
+
+struct Pruned<'a>(&'a Keep);
+
+impl<'de> DeserializeSeed<'de> for Pruned<'_> {
+    type Value = Value;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+        match self.0 {
+            Keep::All => Value::deserialize(deserializer),
+            Keep::Fields(fields) => deserializer.deserialize_any(PrunedVisitor(fields)),
+        }
+    }
+}
+
+struct PrunedVisitor<'a>(&'a HashMap<String, Keep>);
+
+impl<'de> Visitor<'de> for PrunedVisitor<'_> {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("any JSON value")
+    }
+
+    // scalars are kept as is, i.e. of the path, which goes deeper than the document
+    fn visit_bool<E>(self, value: bool) -> Result<Value, E> {
+        Ok(Value::Bool(value))
+    }
+
+    fn visit_i64<E>(self, value: i64) -> Result<Value, E> {
+        Ok(Value::from(value))
+    }
+
+    fn visit_u64<E>(self, value: u64) -> Result<Value, E> {
+        Ok(Value::from(value))
+    }
+
+    fn visit_f64<E>(self, value: f64) -> Result<Value, E> {
+        Ok(Value::from(value))
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Value, E> {
+        Ok(Value::String(value.to_string()))
+    }
+
+    fn visit_string<E>(self, value: String) -> Result<Value, E> {
+        Ok(Value::String(value))
+    }
+
+    fn visit_unit<E>(self) -> Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    // items, which aren't kept, are replaced with nulls, so the length of the array is kept
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+        let mut items = Vec::new();
+        loop {
+            let item = match self.0.get(&items.len().to_string()) {
+                Some(keep) => seq.next_element_seed(Pruned(keep))?,
+                None => seq.next_element::<IgnoredAny>()?.map(|_| Value::Null),
+            };
+            match item {
+                Some(item) => items.push(item),
+                None => return Ok(Value::Array(items)),
+            }
+        }
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Value, A::Error> {
+        let mut object = Map::new();
+        while let Some(key) = map.next_key::<String>()? {
+            match self.0.get(&key) {
+                Some(keep) => {
+                    let value = map.next_value_seed(Pruned(keep))?;
+                    object.insert(key, value);
+                }
+                None => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        Ok(Value::Object(object))
+    }
+}
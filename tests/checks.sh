@@ -0,0 +1,223 @@
+#!/usr/bin/env bash
+# Checks of tstit behavior, which testplans alone can't express: exit codes, reports and logs.
+# Run against fake_server (`cargo r --example fake_server`), with TSTIT_URL and TSTIT_TKN set
+# (`jq` is required for JSON reports):
+#   ➜  cargo b && ./tests/checks.sh
+set -u
+
+TSTIT=${TSTIT:-target/debug/tstit}
+OUT=$(mktemp -d)
+trap 'rm -rf "$OUT"' EXIT
+failed=0
+
+fail() {
+    echo "FAIL: $*"
+    failed=1
+}
+
+# runs tstit, keeping its stdout and stderr in $OUT, and checks its exit code:
+# `expect_exit <code> <args...>`
+expect_exit() {
+    local code=$1
+    shift
+    "$TSTIT" "$@" >"$OUT/stdout" 2>"$OUT/stderr"
+    local actual=$?
+    [ "$actual" -eq "$code" ] || fail "tstit $*: exit code $actual, expected $code"
+}
+
+# checks that the file contains the fixed string: `expect_in <file> <text>`
+expect_in() {
+    grep -qF -- "$2" "$1" || fail "$1 doesn't contain '$2'"
+}
+
+# checks that a line of the file matches extended regex: `expect_match <file> <regex>`
+expect_match() {
+    grep -qE -- "$2" "$1" || fail "$1 doesn't match '$2'"
+}
+
+# checks that jq filter holds for JSON file: `expect_json <file> <filter>`
+expect_json() {
+    jq -e "$2" "$1" >/dev/null || fail "$1 doesn't satisfy '$2'"
+}
+
+# checks that the file doesn't contain the fixed string: `expect_not_in <file> <text>`
+expect_not_in() {
+    ! grep -qF -- "$2" "$1" || fail "$1 contains '$2'"
+}
+
+# exit codes
+expect_exit 0 tests/customer/90health.toml
+expect_exit 1 tests/failing/
+expect_exit 3 --tag no_such_tag tests/customer/
+expect_exit 3 --name no_such_plan tests/customer/
+
+# failed soft assertion is reported, but doesn't fail the testplan
+expect_exit 0 --report-md "$OUT/soft.md" tests/customer/90health_soft.toml
+expect_in "$OUT/stderr" "soft assertion failed"
+expect_in "$OUT/soft.md" "| \`deprecated\` | \`@exists\` | \`<absent>\` | ⚠️ soft |"
+
+# request and expected bodies of env vars
+export TSTIT_ECHO_JSON='{"firstname": "John"}'
+export TSTIT_ECHO_EXPECTED='{"code": 0, "data": {"content_type": "application/json",
+    "text": "{\"firstname\":\"John\"}"}}'
+expect_exit 0 tests/envjson/
+TSTIT_ECHO_EXPECTED='{"code": 0, "data": {"text": "{}"}}' expect_exit 1 tests/envjson/
+expect_in "$OUT/stderr" '/data/content_type: <absent> != "application/json"'
+TSTIT_ECHO_EXPECTED='{"text":' expect_exit 1 tests/envjson/
+expect_in "$OUT/stderr" "env var TSTIT_ECHO_EXPECTED is not a valid JSON"
+env -u TSTIT_ECHO_JSON "$TSTIT" tests/envjson/ >"$OUT/stdout" 2>"$OUT/stderr"
+expect_in "$OUT/stderr" "env var TSTIT_ECHO_JSON with JSON is not set"
+
+# integer doesn't pass for float
+expect_exit 1 tests/failing/52not_float.toml
+expect_in "$OUT/stderr" "'data' field expected 'float:5', but got '5'"
+
+# failing fields as JSON Pointers into the response
+expect_exit 1 --json-pointer-errors tests/failing/31nested_pointer.toml
+expect_in "$OUT/stderr" "field '/result/healthy' expected 'false' but got 'true'"
+expect_exit 1 tests/failing/31nested_pointer.toml
+expect_in "$OUT/stderr" "field 'healthy' expected 'false' but got 'true'"
+
+# mismatches, reported by the matchers themselves, name the field too
+expect_exit 1 tests/failing/32subset_mismatch.toml tests/failing/65array_mismatch.toml \
+    tests/failing/66cmd_rejected.toml
+expect_in "$OUT/stderr" "field 'data': '/content_type' expected \"text/html\", but got \"text/plain\""
+expect_in "$OUT/stderr" "field 'tags': array items differ: missing [\"old\"], extra [\"new\"]"
+expect_in "$OUT/stderr" "field 'text': command 'grep -q deleted' rejected 'customer 1 is created'"
+
+# replayed requests are expected to respond with the recorded status, or any 2xx one
+expect_exit 0 --replay tests/replay/requests.log
+# expectations of the plain response (no envelope), recorded `$word` isn't taken for a var
+expect_exit 0 --replay tests/replay/requests.log --replay-expect code=0
+expect_exit 1 --replay tests/replay/requests.log --replay-expect data.missing=1
+expect_exit 1 --replay tests/replay/failing.log
+expect_in "$OUT/stderr" "HTTP status expected 200, but got 201"
+expect_in "$OUT/stderr" "HTTP status expected 2xx, but got 404"
+
+# generated vars are drawn once per request, so the logged value is the one sent,
+# and `-v` doesn't change the values of `--seed`
+expect_exit 0 --seed 42 tests/seeded/
+cp tests/seeded/out/generated.json "$OUT/generated.json"
+expect_exit 0 -v --seed 42 tests/seeded/
+cmp -s tests/seeded/out/generated.json "$OUT/generated.json" ||
+    fail "-v changes generated vars of the same --seed"
+expect_in "$OUT/stderr" "request body: $(jq -r .data.text "$OUT/generated.json")"
+# and `--explain` prints the very body, which is sent
+expect_exit 0 --explain --seed 42 tests/seeded/
+expect_in "$OUT/stderr" "  body: $(jq -r .data.text "$OUT/generated.json")"
+
+# summary of bench: throughput, error rate and latency percentiles
+expect_exit 0 bench tests/bench/10health.toml --duration 1s --concurrency 4 --check
+expect_match "$OUT/stderr" "requests: [0-9]+, errors: 0 \(0\.00%\), rps: [0-9]+\.[0-9]"
+expect_match "$OUT/stderr" "latency p50: [0-9.]+[^ ,]+, p90: [0-9.]+[^ ,]+, p99: [0-9.]+[^ ,]+, max: [0-9.]+"
+
+# JUnit report of a failed and a skipped testplan
+env -u TSTIT_ENV "$TSTIT" --report-junit "$OUT/junit.xml" tests/failing/52not_float.toml \
+    tests/customer/85skipped_locally.toml >"$OUT/stdout" 2>"$OUT/stderr"
+[ $? -eq 1 ] || fail "failed testplan doesn't fail the run of JUnit report"
+expect_in "$OUT/junit.xml" '<testsuites tests="2" failures="1"'
+expect_in "$OUT/junit.xml" 'tests="2" failures="1" skipped="1"'
+expect_match "$OUT/junit.xml" '<failure message="[^"]*float:5[^"]*">'
+expect_in "$OUT/junit.xml" '<skipped message="skip_if ${TSTIT_ENV:-local} == local holds"/>'
+
+# `#` of testplan names is escaped by TAP
+expect_exit 0 --format tap tests/tap/10echo_hashes.toml
+expect_in "$OUT/stdout" 'ok 1 - tests/tap/10echo_hashes.toml [ISSUE=\#1 SKIP]'
+expect_in "$OUT/stdout" 'ok 2 - tests/tap/10echo_hashes.toml [ISSUE=\#2]'
+expect_not_in "$OUT/stdout" '[ISSUE=#'
+
+# JSON document of the run: totals and per testplan fields
+env -u TSTIT_ENV "$TSTIT" --format json tests/customer/90health.toml tests/failing/52not_float.toml \
+    tests/customer/85skipped_locally.toml >"$OUT/run.json" 2>"$OUT/stderr"
+[ $? -eq 1 ] || fail "failed testplan doesn't fail the run of JSON format"
+expect_json "$OUT/run.json" '.totals | .total == 3 and .passed == 1 and .failed == 1
+    and .skipped == 1 and (.duration_ms | type) == "number"'
+expect_json "$OUT/run.json" '.results[] | select(.path == "tests/customer/90health.toml")
+    | .status == "passed" and .http_status == 200 and .error == null'
+expect_json "$OUT/run.json" '.results[] | select(.path == "tests/failing/52not_float.toml")
+    | .status == "failed" and .http_status == 200 and (.error | contains("float:5"))'
+expect_json "$OUT/run.json" '.results[] | select(.path == "tests/customer/85skipped_locally.toml")
+    | .status == "skipped" and .http_status == null'
+
+# testplan vars with `vars_override` beat both env file and process env
+expect_exit 0 --env-file tests/envfile/.env tests/envfile/
+TSTIT_DELAY_MS=60 expect_exit 0 --env-file tests/envfile/.env tests/envfile/30vars_override.toml
+TSTIT_DELAY_MS=60 expect_exit 0 --env-file tests/envfile/.env --env-file-override \
+    tests/envfile/30vars_override.toml
+
+# proxy of env vars, or of the testplan: the host of testplans is resolvable through the proxy only
+http_proxy=$TSTIT_URL expect_exit 0 tests/proxy/
+HTTP_PROXY=$TSTIT_URL expect_exit 0 tests/proxy/20health_http.toml
+# curl ignores uppercase HTTP_PROXY
+HTTP_PROXY=$TSTIT_URL expect_exit 1 tests/proxy/10health_curl.toml
+expect_in "$OUT/stderr" "Could not resolve host: tstit-proxied.invalid"
+expect_exit 0 tests/proxy/30health_plan_proxy.toml tests/proxy/40health_plan_proxy_http.toml
+
+# TLS verification is skipped with `insecure` only, warning once per testplan; needs openssl
+if command -v openssl >/dev/null; then
+    mkdir "$OUT/tls"
+    openssl req -x509 -newkey rsa:2048 -nodes -days 1 -subj /CN=127.0.0.1 \
+        -keyout "$OUT/tls/key.pem" -out "$OUT/tls/cert.pem" 2>/dev/null
+    printf 'HTTP/1.0 200 OK\r\nContent-Type: application/json\r\n\r\n%s\n' \
+        '{"code": 0, "data": {"healthy": true}}' >"$OUT/tls/health"
+    (cd "$OUT/tls" && exec openssl s_server -quiet -HTTP -accept 8443 -cert cert.pem \
+        -key key.pem >/dev/null 2>&1) &
+    tls_pid=$!
+    sleep 1
+    export TSTIT_TLS_URL=https://127.0.0.1:8443
+    expect_exit 0 tests/tls/
+    [ "$(grep -c "TLS certificate verification is disabled" "$OUT/stderr")" -eq 2 ] ||
+        fail "TLS warning isn't logged once per testplan"
+    expect_exit 1 tests/tls_verified/
+    expect_in "$OUT/stderr" "self-signed certificate"
+    kill "$tls_pid"
+else
+    echo "SKIP: TLS checks, openssl is not found"
+fi
+
+# self-contained HTML report, with colored diffs and masked token
+expect_exit 1 --report-html "$OUT/report.html" tests/failing/30nested_mismatch.toml \
+    tests/failing/67leaked_token.toml
+for asset in "<script" "<link" "<img" "src=" "url(" "@import"; do
+    expect_not_in "$OUT/report.html" "$asset"
+done
+expect_in "$OUT/report.html" '<span class="del">  - '
+expect_in "$OUT/report.html" '<span class="ins">  + '
+expect_in "$OUT/report.html" "token ***"
+expect_not_in "$OUT/report.html" "$TSTIT_TKN"
+
+# verbose log redacts the token, credentials and secrets echoed back
+expect_exit 0 -v tests/customer/90health.toml tests/customer/98api_key_auth.toml
+expect_in "$OUT/stderr" "request header: Authorization: ***"
+expect_in "$OUT/stderr" "request header: X-Api-Key: ***"
+expect_not_in "$OUT/stderr" "$TSTIT_TKN"
+expect_not_in "$OUT/stderr" "tstit-key"
+expect_exit 1 -v tests/failing/67leaked_token.toml
+expect_in "$OUT/stderr" "token ***"
+expect_not_in "$OUT/stderr" "$TSTIT_TKN"
+expect_exit 1 -v tests/failing/68leaked_password.toml
+expect_in "$OUT/stderr" "request header: Authorization: Basic ***"
+expect_in "$OUT/stderr" '"-u" "tstit:***"'
+expect_not_in "$OUT/stderr" "tstit-password"
+
+# credentials of the testplan are masked in every report and in the logged errors
+for leaked in 68leaked_password:tstit-password 69leaked_bearer:tstit-bearer \
+    70leaked_api_key:tstit-api-key; do
+    plan="tests/failing/${leaked%%:*}.toml" secret="${leaked#*:}"
+    expect_exit 1 --report-md "$OUT/leaked.md" --report-junit "$OUT/leaked.xml" \
+        --report-html "$OUT/leaked.html" "$plan"
+    for report in "$OUT/stderr" "$OUT/leaked.md" "$OUT/leaked.xml" "$OUT/leaked.html"; do
+        expect_in "$report" "leaked ***"
+        expect_not_in "$report" "$secret"
+    done
+    for format in tap json; do
+        expect_exit 1 --format "$format" "$plan"
+        expect_in "$OUT/stdout" "leaked ***"
+        expect_not_in "$OUT/stdout" "$secret"
+    done
+done
+
+if [ "$failed" -eq 0 ]; then
+    echo "all checks passed"
+fi
+exit "$failed"